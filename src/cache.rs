@@ -1,33 +1,261 @@
 /// This module provides a local cache of web URLs. It is intended to be the equivalent of
-/// a browser's cache. It currently doesn't expire any entries in the cache.
+/// a browser's cache. Entries expire according to the `[cache]` section of the config: a
+/// default time to live plus overrides for urls matching a pattern. Expired entries are
+/// refetched on access, falling back to the stale copy when the fetch fails.
 ///
-/// TODO:
-/// - Allow for a forced overwrite of a cache entry
-/// - Enable preservation of old copies of cache entries
-/// - Provide a means to return old cached copies of entries
-/// - Use this capability to backup old copies of the humble bundle monthly feed
+/// Each entry is stored as `<root>/<sha256 of url>` with the url itself in a `<hash>.url`
+/// sidecar. When an entry is refetched, the previous body is preserved next to it as
+/// `<hash>.<unix seconds>` so that old copies (e.g. of the humble bundle monthly feed)
+/// can still be retrieved later.
+///
+/// Urls are retrieved through a `Fetcher`, the network by default. `Cache::with_fetcher`
+/// substitutes another, e.g. a `FileFetcher` serving recorded pages.
+///
+/// Response headers needed for revalidation are kept in a `<hash>.meta` sidecar. Stale entries
+/// are revalidated with `If-None-Match`/`If-Modified-Since`; a 304 response only refreshes the
+/// entry's fetch time. Urls that answered 404 or 410 are remembered in the sidecar as negative
+/// entries so they are not requested again until they expire.
+///
+/// Bodies and sidecars are written to a temporary file and renamed into place. The sidecar
+/// records the length and SHA-256 of the body; entries that no longer match are refetched.
+///
+/// Requests are paced per host according to `requests_per_second`, and responses of 429 or
+/// 5xx are retried with exponential backoff. Neither the backoff nor a `Retry-After` header
+/// waits longer than `max_backoff`.
+///
+/// `retrieve_all` fetches many urls at once with a bounded pool of `concurrency` workers,
+/// reporting aggregate progress as it goes.
+///
+/// In offline mode the network is never touched: stale entries are served as they are and
+/// urls that were never cached fail with `CacheError::NotCached`.
+///
+/// The modification time of the `<hash>.url` sidecar records the last access of an entry so
+/// that the cache can be pruned to a size budget, least recently used first.
 extern crate sha2;
 extern crate log;
 
+use std::collections::HashMap;
 use std::path::PathBuf;
 use sha2::Digest;
-use std::fs;
-use std::io::{Read, Error};
-use log::{debug, error};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+use std::io::{self, ErrorKind};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+use crate::config;
+use crate::fetcher::{Fetcher, HttpFetcher, Request, Response};
+use crate::util::write_atomic;
 
-fn sha256(url: &str) -> String {
+fn sha256(bytes: &[u8]) -> String {
     let mut hasher = sha2::Sha256::new();
-    hasher.input(url.as_bytes());
+    hasher.input(bytes);
     hex::encode(&hasher.result())
 }
 
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[derive(Debug)]
+pub enum CacheError {
+    Network(reqwest::Error),
+    Status { code: u16, url: String },
+    Io(io::Error),
+    InvalidUrl(url::ParseError),
+    NotCached(String),
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CacheError::Network(err) => write!(f, "network error: {}", err),
+            CacheError::Status { code, url } => write!(f, "HTTP {}: {}", code, url),
+            CacheError::Io(err) => write!(f, "io error: {}", err),
+            CacheError::InvalidUrl(err) => write!(f, "invalid url: {}", err),
+            CacheError::NotCached(url) => write!(f, "not cached (offline): {}", url),
+        }
+    }
+}
+
+impl std::error::Error for CacheError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CacheError::Network(err) => Some(err),
+            CacheError::Status { .. } => None,
+            CacheError::Io(err) => Some(err),
+            CacheError::InvalidUrl(err) => Some(err),
+            CacheError::NotCached(_) => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for CacheError {
+    fn from(err: reqwest::Error) -> Self {
+        CacheError::Network(err)
+    }
+}
+
+impl From<io::Error> for CacheError {
+    fn from(err: io::Error) -> Self {
+        CacheError::Io(err)
+    }
+}
+
+impl From<url::ParseError> for CacheError {
+    fn from(err: url::ParseError) -> Self {
+        CacheError::InvalidUrl(err)
+    }
+}
+
+impl From<CacheError> for io::Error {
+    fn from(err: CacheError) -> Self {
+        match err {
+            CacheError::Io(err) => err,
+            err => io::Error::new(ErrorKind::Other, err),
+        }
+    }
+}
+
+/// Statuses that mean the url is gone rather than temporarily unavailable.
+fn is_gone(code: u16) -> bool {
+    code == 404 || code == 410
+}
+
+/// The contents of the `<hash>.meta` sidecar.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Meta {
+    /// Unix seconds of the last time the entry was fetched or revalidated.
+    pub fetched: u64,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub content_type: Option<String>,
+    /// Set for negative entries: the status the url answered with instead of a body.
+    #[serde(default)]
+    pub status: Option<u16>,
+    #[serde(default)]
+    pub length: Option<u64>,
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+impl Meta {
+    fn from_response(response: &Response) -> Meta {
+        Meta {
+            fetched: unix_secs(SystemTime::now()),
+            etag: response.etag.clone(),
+            last_modified: response.last_modified.clone(),
+            content_type: response.content_type.clone(),
+            status: None,
+            length: None,
+            sha256: None,
+        }
+    }
+
+    /// Checks `body` against the recorded length and hash. Entries cached before these were
+    /// recorded cannot be checked and are assumed to be intact.
+    fn verify(&self, body: &[u8]) -> bool {
+        if let Some(length) = self.length {
+            if length != body.len() as u64 {
+                return false;
+            }
+        }
+        match &self.sha256 {
+            Some(hash) => *hash == sha256(body),
+            None => true,
+        }
+    }
+}
+
+enum Fetched {
+    Body(Vec<u8>, Meta),
+    NotModified,
+}
+
+/// A previously retrieved copy of a cache entry.
+#[derive(Debug, Clone)]
+pub struct Version {
+    pub fetched: SystemTime,
+    pub path: PathBuf,
+}
+
+/// A summary of a single url in the cache.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub url: String,
+    pub hash: String,
+    /// Size of the current body. Negative entries have no body.
+    pub size: u64,
+    /// Combined size of the preserved versions.
+    pub versions_size: u64,
+    pub versions: usize,
+    pub fetched: SystemTime,
+    pub accessed: SystemTime,
+    pub content_type: Option<String>,
+    pub status: Option<u16>,
+}
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub entries: usize,
+    pub negative: usize,
+    pub versions: usize,
+    pub size: u64,
+    pub versions_size: u64,
+    pub oldest: Option<SystemTime>,
+    pub newest: Option<SystemTime>,
+}
+
+/// The outcome of `Cache::retrieve_all`.
+#[derive(Debug, Default)]
+pub struct Progress {
+    pub total: usize,
+    pub completed: usize,
+    pub bytes: u64,
+    pub failures: Vec<(String, CacheError)>,
+}
+
+impl Progress {
+    fn print(&self) {
+        print!(
+            "\r{}/{} {} {} failed",
+            self.completed,
+            self.total,
+            crate::util::format_size(self.bytes),
+            self.failures.len()
+        );
+        let _ = std::io::stdout().flush();
+    }
+}
+
 pub struct Cache {
     root: PathBuf,
+    config: config::Cache,
+    fetcher: Box<dyn Fetcher>,
+    /// The earliest time the next request to each host may be sent.
+    schedule: Mutex<HashMap<String, Instant>>,
 }
 
 impl Cache {
-    pub fn new<T: Into<PathBuf>>(root: T) -> Cache {
-        let cache = Cache { root: root.into() };
+    pub fn new<T: Into<PathBuf>>(root: T, config: &config::Cache) -> Cache {
+        Cache::with_fetcher(root, config, Box::new(HttpFetcher::new()))
+    }
+
+    /// Creates a cache that retrieves urls through `fetcher` instead of the network.
+    pub fn with_fetcher<T: Into<PathBuf>>(root: T, config: &config::Cache, fetcher: Box<dyn Fetcher>) -> Cache {
+        let cache = Cache {
+            root: root.into(),
+            config: config.clone(),
+            fetcher: fetcher,
+            schedule: Mutex::new(HashMap::new()),
+        };
         if !cache.root.exists() {
             debug!("creating: {}", cache.root.display());
             if let Err(result) = fs::create_dir_all(&cache.root) {
@@ -37,35 +265,546 @@ impl Cache {
         return cache;
     }
 
-    pub fn retrieve(&self, url: &str) -> Result<Vec<u8>, Error> {
-        let hash = sha256(url);
+    pub fn is_offline(&self) -> bool {
+        self.config.offline
+    }
+
+    pub fn retrieve(&self, url: &str) -> Result<Vec<u8>, CacheError> {
+        let hash = sha256(url.as_bytes());
         let cached = self.root.join(&hash);
         debug!("{:?}", hash);
-        if !cached.exists() {
-            // TODO: Add cache expiration
+        let meta = self.read_meta(&hash);
+        let gone = meta.as_ref().and_then(|meta| meta.status);
+        if !cached.exists() && gone.is_none() {
             debug!("caching: {}", url);
-            let mut resp = reqwest::get(url).unwrap();
-            assert!(resp.status().is_success());
-            let mut buffer = Vec::new();
-            resp.read_to_end(&mut buffer)?;
-            fs::write(&cached, buffer)?;
-            fs::write(self.root.join(format!("{}.url", &hash)), url)?;
+            return self.refresh(&hash, url, None);
+        }
+        if self.is_expired(url, &hash)? {
+            debug!("expired: {}", url);
+            match self.refresh(&hash, url, meta.as_ref()) {
+                Err(err) if cached.exists() => warn!("using stale copy of {}: {}", url, err),
+                result => return result,
+            }
+        }
+        if let Some(code) = gone {
+            debug!("negative entry: {}", url);
+            return Err(CacheError::Status {
+                code,
+                url: url.to_string(),
+            });
+        }
+        let buffer = fs::read(cached)?;
+        if !self.read_meta(&hash).map_or(true, |meta| meta.verify(&buffer)) {
+            warn!("corrupt cache entry, refetching: {}", url);
+            return self.refresh(&hash, url, None);
+        }
+        self.touch(&hash);
+        Ok(buffer)
+    }
+
+    /// Retrieves every url in `urls` using `concurrency` workers, passing each index and body
+    /// to `handle`. Failed retrievals and handlers are collected rather than stopping the batch.
+    pub fn retrieve_all<F>(&self, urls: &[String], handle: F) -> Progress
+    where
+        F: Fn(usize, &[u8]) -> Result<(), CacheError> + Sync,
+    {
+        let next = AtomicUsize::new(0);
+        let progress = Mutex::new(Progress {
+            total: urls.len(),
+            ..Progress::default()
+        });
+        let workers = self.config.concurrency.max(1).min(urls.len().max(1));
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let url = match urls.get(index) {
+                        Some(url) => url,
+                        None => break,
+                    };
+                    let result = self
+                        .retrieve(url)
+                        .and_then(|body| handle(index, &body).map(|_| body.len() as u64));
+                    let mut progress = progress.lock().unwrap();
+                    progress.completed += 1;
+                    match result {
+                        Ok(bytes) => progress.bytes += bytes,
+                        Err(err) => progress.failures.push((url.clone(), err)),
+                    }
+                    progress.print();
+                });
+            }
+        });
+        println!();
+        progress.into_inner().unwrap()
+    }
+
+    /// Records an access of `hash` for least recently used pruning.
+    fn touch(&self, hash: &str) {
+        let sidecar = self.root.join(format!("{}.url", hash));
+        let result = OpenOptions::new()
+            .write(true)
+            .open(&sidecar)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        if let Err(err) = result {
+            debug!("touching {}: {}", sidecar.display(), err);
+        }
+    }
+
+    /// Returns every url in the cache, including negative entries.
+    pub fn entries(&self) -> impl Iterator<Item = Entry> {
+        let mut names = Vec::new();
+        match fs::read_dir(&self.root) {
+            Ok(dir) => names.extend(
+                dir.filter_map(|entry| entry.ok())
+                    .filter_map(|entry| entry.file_name().into_string().ok()),
+            ),
+            Err(err) => error!("error: {:?}", err),
+        }
+        let mut versions = HashMap::<&str, (usize, u64)>::new();
+        for name in &names {
+            let mut parts = name.splitn(2, '.');
+            let hash = parts.next().unwrap_or_default();
+            if parts.next().map_or(false, |suffix| suffix.parse::<u64>().is_ok()) {
+                let size = fs::metadata(self.root.join(name)).map_or(0, |m| m.len());
+                let version = versions.entry(hash).or_default();
+                version.0 += 1;
+                version.1 += size;
+            }
         }
-        Ok(fs::read(cached)?)
+        let entries: Vec<Entry> = names
+            .iter()
+            .filter_map(|name| name.strip_suffix(".url"))
+            .filter_map(|hash| {
+                let sidecar = self.root.join(format!("{}.url", hash));
+                let url = fs::read_to_string(&sidecar).ok()?;
+                let accessed = fs::metadata(&sidecar).and_then(|m| m.modified()).ok()?;
+                let meta = self.read_meta(hash).unwrap_or_default();
+                let size = fs::metadata(self.root.join(hash)).map_or(0, |m| m.len());
+                let (count, versions_size) = versions.get(hash).cloned().unwrap_or_default();
+                Some(Entry {
+                    url,
+                    hash: hash.to_string(),
+                    size,
+                    versions_size,
+                    versions: count,
+                    fetched: self.fetched(hash).unwrap_or(accessed),
+                    accessed,
+                    content_type: meta.content_type,
+                    status: meta.status,
+                })
+            })
+            .collect();
+        entries.into_iter()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.entries().fold(Stats::default(), |mut stats, entry| {
+            stats.entries += 1;
+            if entry.status.is_some() {
+                stats.negative += 1;
+            }
+            stats.versions += entry.versions;
+            stats.size += entry.size;
+            stats.versions_size += entry.versions_size;
+            stats.oldest = Some(stats.oldest.map_or(entry.fetched, |t| t.min(entry.fetched)));
+            stats.newest = Some(stats.newest.map_or(entry.fetched, |t| t.max(entry.fetched)));
+            stats
+        })
+    }
+
+    /// Removes every entry last fetched more than `age` ago.
+    pub fn prune_older_than(&self, age: Duration) -> Result<Vec<Entry>, CacheError> {
+        let cutoff = match SystemTime::now().checked_sub(age) {
+            Some(cutoff) => cutoff,
+            // Nothing can have been fetched before the earliest representable time.
+            None => return Ok(Vec::new()),
+        };
+        self.prune(self.entries().filter(|entry| entry.fetched < cutoff).collect())
+    }
+
+    /// Removes the least recently used entries until the cache, versions included, fits
+    /// within `budget` bytes.
+    pub fn prune_to_size(&self, budget: u64) -> Result<Vec<Entry>, CacheError> {
+        let mut entries: Vec<Entry> = self.entries().collect();
+        entries.sort_by_key(|entry| entry.accessed);
+        let mut total: u64 = entries.iter().map(|e| e.size + e.versions_size).sum();
+        let doomed = entries
+            .into_iter()
+            .take_while(|entry| {
+                let over = total > budget;
+                total -= entry.size + entry.versions_size;
+                over
+            })
+            .collect();
+        self.prune(doomed)
     }
 
-    pub fn get_versions(&self, _url: &str) -> Vec<String> {
-        Vec::<String>::new()
+    /// Removes every entry whose url starts with `prefix`.
+    pub fn prune_prefix(&self, prefix: &str) -> Result<Vec<Entry>, CacheError> {
+        self.prune(self.entries().filter(|entry| entry.url.starts_with(prefix)).collect())
     }
 
-    pub fn retrieve_version(&self, _index: u32) {}
+    /// Deletes the body, sidecars and versions of each entry.
+    fn prune(&self, entries: Vec<Entry>) -> Result<Vec<Entry>, CacheError> {
+        for dir_entry in fs::read_dir(&self.root)? {
+            let dir_entry = dir_entry?;
+            let name = dir_entry.file_name();
+            let name = name.to_string_lossy();
+            let hash = name.split('.').next().unwrap_or_default();
+            if entries.iter().any(|entry| entry.hash == hash) {
+                debug!("pruning: {}", name);
+                fs::remove_file(dir_entry.path())?;
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Returns the time to live of `url`. The first policy whose pattern occurs in the url
+    /// wins, otherwise the default applies. `None` means the entry never expires.
+    pub fn ttl(&self, url: &str) -> Option<Duration> {
+        let ttl = match self.config.policies.iter().find(|p| url.contains(&p.pattern)) {
+            Some(policy) => policy.ttl,
+            None => self.config.ttl,
+        };
+        ttl.map(Duration::from_secs)
+    }
+
+    fn is_expired(&self, url: &str, hash: &str) -> Result<bool, CacheError> {
+        if self.config.offline {
+            return Ok(false);
+        }
+        // A url that was gone may be back, so negative entries expire even without a ttl.
+        let negative = self.read_meta(hash).map_or(false, |meta| meta.status.is_some());
+        let ttl = match (self.ttl(url), negative) {
+            (Some(ttl), true) => ttl.min(Duration::from_secs(self.config.negative_ttl)),
+            (None, true) => Duration::from_secs(self.config.negative_ttl),
+            (Some(ttl), false) => ttl,
+            (None, false) => return Ok(false),
+        };
+        let fetched = self.fetched(hash)?;
+        Ok(match SystemTime::now().duration_since(fetched) {
+            Ok(age) => age > ttl,
+            Err(_) => false,
+        })
+    }
+
+    /// Returns the preserved copies of `url`, oldest first. The current copy is not included.
+    pub fn get_versions(&self, url: &str) -> Vec<Version> {
+        let prefix = format!("{}.", sha256(url.as_bytes()));
+        let entries = match fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(err) => {
+                error!("error: {:?}", err);
+                return Vec::new();
+            }
+        };
+        let mut versions: Vec<Version> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                let secs = name.strip_prefix(&prefix)?.parse::<u64>().ok()?;
+                Some(Version {
+                    fetched: UNIX_EPOCH + Duration::from_secs(secs),
+                    path: entry.path(),
+                })
+            })
+            .collect();
+        versions.sort_by_key(|v| v.fetched);
+        versions
+    }
 
-    pub fn force_retrieve(&self, url: &str) -> Result<Vec<u8>, Error> {
-        let hash = sha256(url);
+    /// Returns the current copy of `url` without fetching or revalidating it.
+    pub fn retrieve_cached(&self, url: &str) -> Result<Vec<u8>, CacheError> {
+        let hash = sha256(url.as_bytes());
         let cached = self.root.join(&hash);
         if cached.exists() {
-            fs::remove_file(cached)?;
+            return Ok(fs::read(cached)?);
+        }
+        match self.read_meta(&hash).and_then(|meta| meta.status) {
+            Some(code) => Err(CacheError::Status {
+                code,
+                url: url.to_string(),
+            }),
+            None => Err(CacheError::NotCached(url.to_string())),
+        }
+    }
+
+    /// Returns the body of the `index`th preserved copy of `url` as listed by `get_versions`.
+    pub fn retrieve_version(&self, url: &str, index: usize) -> Result<Vec<u8>, CacheError> {
+        match self.get_versions(url).get(index) {
+            Some(version) => Ok(fs::read(&version.path)?),
+            None => Err(CacheError::Io(io::Error::new(
+                ErrorKind::NotFound,
+                format!("no version {} of {}", index, url),
+            ))),
+        }
+    }
+
+    /// Refetches `url`, keeping the previously cached copy as a version.
+    pub fn force_retrieve(&self, url: &str) -> Result<Vec<u8>, CacheError> {
+        let hash = sha256(url.as_bytes());
+        debug!("refreshing: {}", url);
+        self.refresh(&hash, url, None)
+    }
+
+    /// When the cached copy of `url` was last fetched or revalidated.
+    pub fn fetched_at(&self, url: &str) -> Result<SystemTime, CacheError> {
+        self.fetched(&sha256(url.as_bytes()))
+    }
+
+    /// Returns the sidecar of `hash`, if it has one.
+    pub fn read_meta(&self, hash: &str) -> Option<Meta> {
+        let bytes = fs::read(self.root.join(format!("{}.meta", hash))).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    fn write_meta(&self, hash: &str, meta: &Meta) -> Result<(), CacheError> {
+        write_atomic(
+            &self.root.join(format!("{}.meta", hash)),
+            &serde_json::to_vec(meta).map_err(io::Error::from)?,
+        )?;
+        Ok(())
+    }
+
+    /// Returns when `hash` was last fetched. Entries cached before sidecars existed fall
+    /// back to the modification time of the body.
+    fn fetched(&self, hash: &str) -> Result<SystemTime, CacheError> {
+        match self.read_meta(hash) {
+            Some(meta) => Ok(UNIX_EPOCH + Duration::from_secs(meta.fetched)),
+            None => Ok(fs::metadata(self.root.join(hash))?.modified()?),
+        }
+    }
+
+    /// Fetches `url` and updates its entry. A url that is gone and has no cached body is
+    /// recorded as a negative entry.
+    fn refresh(&self, hash: &str, url: &str, validators: Option<&Meta>) -> Result<Vec<u8>, CacheError> {
+        match self.fetch(url, validators) {
+            Ok(Fetched::Body(buffer, meta)) => {
+                self.store(hash, url, &buffer, &meta)?;
+                Ok(buffer)
+            }
+            Ok(Fetched::NotModified) => {
+                debug!("not modified: {}", url);
+                let mut meta = validators.cloned().unwrap_or_default();
+                meta.fetched = unix_secs(SystemTime::now());
+                self.write_meta(hash, &meta)?;
+                Ok(fs::read(self.root.join(hash))?)
+            }
+            Err(CacheError::Status { code, url }) => {
+                if is_gone(code) && !self.root.join(hash).exists() {
+                    debug!("remembering {} for {}", code, url);
+                    let meta = Meta {
+                        fetched: unix_secs(SystemTime::now()),
+                        status: Some(code),
+                        ..Meta::default()
+                    };
+                    write_atomic(&self.root.join(format!("{}.url", hash)), url.as_bytes())?;
+                    self.write_meta(hash, &meta)?;
+                }
+                Err(CacheError::Status { code, url })
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Waits until another request may be sent to the host of `url`.
+    fn throttle(&self, url: &str) {
+        let host = match url::Url::parse(url) {
+            Ok(parsed) => parsed.host_str().unwrap_or_default().to_string(),
+            Err(_) => return,
+        };
+        let rate = match self.config.host_requests_per_second.get(&host) {
+            Some(rate) => *rate,
+            None => match self.config.requests_per_second {
+                Some(rate) => rate,
+                None => return,
+            },
+        };
+        if rate <= 0.0 {
+            return;
+        }
+        let now = Instant::now();
+        let wait = {
+            let mut schedule = self.schedule.lock().unwrap();
+            let next = schedule.entry(host).or_insert(now);
+            let start = (*next).max(now);
+            *next = start + Duration::from_secs_f64(1.0 / rate);
+            start - now
+        };
+        if wait > Duration::from_secs(0) {
+            debug!("throttling {:?}: {}", wait, url);
+            thread::sleep(wait);
+        }
+    }
+
+    /// Requests `url`, making the request conditional when `validators` has an etag or
+    /// last modified date.
+    fn fetch(&self, url: &str, validators: Option<&Meta>) -> Result<Fetched, CacheError> {
+        if self.config.offline {
+            return Err(CacheError::NotCached(url.to_string()));
+        }
+        url::Url::parse(url)?;
+        let request = Request {
+            url: url,
+            etag: validators.and_then(|meta| meta.etag.as_deref()),
+            last_modified: validators.and_then(|meta| meta.last_modified.as_deref()),
+        };
+        let mut attempt = 0;
+        let response = loop {
+            self.throttle(url);
+            let response = self.fetcher.fetch(&request)?;
+            let retryable = response.status == 429 || response.status >= 500;
+            if !retryable || attempt >= self.config.retries {
+                break response;
+            }
+            let delay = match response.retry_after {
+                Some(secs) => Duration::from_secs(secs),
+                None => Duration::from_millis(
+                    self.config
+                        .backoff
+                        .saturating_mul(1u64.checked_shl(attempt).unwrap_or(u64::MAX)),
+                ),
+            };
+            let delay = delay.min(Duration::from_millis(self.config.max_backoff));
+            warn!("HTTP {}, retrying in {:?}: {}", response.status, delay, url);
+            thread::sleep(delay);
+            attempt += 1;
+        };
+        match response.status {
+            304 => Ok(Fetched::NotModified),
+            200..=299 => {
+                let meta = Meta::from_response(&response);
+                Ok(Fetched::Body(response.body, meta))
+            }
+            code => Err(CacheError::Status {
+                code,
+                url: url.to_string(),
+            }),
+        }
+    }
+
+    /// Writes `buffer` as the current copy of `url`. An existing intact copy with different
+    /// contents is renamed to `<hash>.<unix seconds of its last fetch>` first.
+    fn store(&self, hash: &str, url: &str, buffer: &[u8], meta: &Meta) -> Result<(), CacheError> {
+        let cached = self.root.join(hash);
+        if cached.exists() {
+            let previous = fs::read(&cached)?;
+            let intact = self.read_meta(hash).map_or(true, |meta| meta.verify(&previous));
+            if intact && previous != buffer {
+                let fetched = self.fetched(hash)?;
+                let version = self.root.join(format!("{}.{}", hash, unix_secs(fetched)));
+                debug!("preserving: {}", version.display());
+                fs::rename(&cached, version)?;
+            }
+        }
+        let meta = Meta {
+            length: Some(buffer.len() as u64),
+            sha256: Some(sha256(buffer)),
+            ..meta.clone()
+        };
+        write_atomic(&cached, buffer)?;
+        write_atomic(&self.root.join(format!("{}.url", hash)), url.as_bytes())?;
+        self.write_meta(hash, &meta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_dir;
+    use std::sync::Arc;
+
+    const URL: &str = "https://www.humblebundle.com/api/v1/trove/chunk?index=0";
+
+    /// Answers with `responses` in order, recording the etag sent with each request.
+    struct Stub {
+        responses: Mutex<Vec<Response>>,
+        etags: Arc<Mutex<Vec<Option<String>>>>,
+    }
+
+    impl Fetcher for Stub {
+        fn fetch(&self, request: &Request) -> Result<Response, CacheError> {
+            self.etags.lock().unwrap().push(request.etag.map(|etag| etag.to_string()));
+            Ok(self.responses.lock().unwrap().remove(0))
         }
-        self.retrieve(url)
+    }
+
+    fn response(status: u16, etag: Option<&str>, body: &[u8]) -> Response {
+        Response {
+            status,
+            etag: etag.map(|etag| etag.to_string()),
+            last_modified: None,
+            content_type: None,
+            retry_after: None,
+            body: body.to_vec(),
+        }
+    }
+
+    fn stubbed(name: &str, ttl: Option<u64>, responses: Vec<Response>) -> (Cache, Arc<Mutex<Vec<Option<String>>>>) {
+        let etags = Arc::new(Mutex::new(Vec::new()));
+        let stub = Stub {
+            responses: Mutex::new(responses),
+            etags: etags.clone(),
+        };
+        let config = config::Cache {
+            ttl,
+            ..config::Cache::default()
+        };
+        (Cache::with_fetcher(test_dir(name), &config, Box::new(stub)), etags)
+    }
+
+    #[test]
+    fn not_found_is_remembered() {
+        let (cache, etags) = stubbed("cache-404", Some(86400), vec![response(404, None, b"")]);
+        for _ in 0..2 {
+            match cache.retrieve(URL) {
+                Err(CacheError::Status { code: 404, .. }) => {}
+                other => panic!("expected 404, got {:?}", other),
+            }
+        }
+        assert_eq!(etags.lock().unwrap().len(), 1);
+        let meta = cache.read_meta(&sha256(URL.as_bytes())).unwrap();
+        assert_eq!(meta.status, Some(404));
+        let entries: Vec<Entry> = cache.entries().collect();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].url, URL);
+    }
+
+    #[test]
+    fn not_found_expires_without_ttl() {
+        let (cache, etags) = stubbed("cache-404-expiry", None, vec![response(404, None, b""), response(200, None, b"[]")]);
+        assert!(cache.retrieve(URL).is_err());
+        let hash = sha256(URL.as_bytes());
+        let meta = Meta {
+            fetched: 0,
+            ..cache.read_meta(&hash).unwrap()
+        };
+        cache.write_meta(&hash, &meta).unwrap();
+        assert_eq!(cache.retrieve(URL).unwrap(), b"[]");
+        assert_eq!(etags.lock().unwrap().len(), 2);
+        assert_eq!(cache.read_meta(&hash).unwrap().status, None);
+    }
+
+    #[test]
+    fn not_modified_revalidates() {
+        let (cache, etags) = stubbed(
+            "cache-304",
+            Some(86400),
+            vec![response(200, Some("\"v1\""), b"[]"), response(304, None, b"")],
+        );
+        assert_eq!(cache.retrieve(URL).unwrap(), b"[]");
+        let hash = sha256(URL.as_bytes());
+        let meta = Meta {
+            fetched: 0,
+            ..cache.read_meta(&hash).unwrap()
+        };
+        cache.write_meta(&hash, &meta).unwrap();
+        assert_eq!(cache.retrieve(URL).unwrap(), b"[]");
+        assert_eq!(*etags.lock().unwrap(), [None, Some("\"v1\"".to_string())]);
+        let meta = cache.read_meta(&hash).unwrap();
+        assert!(meta.fetched > 0);
+        assert_eq!(meta.etag.as_deref(), Some("\"v1\""));
+        assert!(cache.get_versions(URL).is_empty());
     }
 }
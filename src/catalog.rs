@@ -0,0 +1,139 @@
+/// This module keeps a persistent record of every product that has ever appeared in the trove
+/// feed. Games are periodically pulled from the trove; the catalog keeps their metadata along
+/// with when they were first and last seen, so that titles which disappeared can still be
+/// listed.
+use crate::trove_feed::Product;
+use crate::util::write_atomic;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CatalogEntry {
+    pub product: Product,
+    /// Dates are `YYYY-MM-DD` in local time.
+    pub first_seen: String,
+    pub last_seen: String,
+    pub removed: bool,
+    /// The first fetch the product was missing from.
+    pub removed_on: Option<String>,
+    /// How many times the product has rotated out of the trove, including now.
+    #[serde(default)]
+    pub removals: u32,
+    /// The first missing fetch of every time the product rotated out, oldest first. Unlike
+    /// `removed_on` these are kept when the product returns.
+    #[serde(default)]
+    pub removal_dates: Vec<String>,
+}
+
+pub struct Catalog {
+    path: PathBuf,
+    pub entries: BTreeMap<String, CatalogEntry>,
+}
+
+impl Catalog {
+    /// Loads `catalog.json` from `root`, starting an empty catalog if there is none.
+    pub fn load(root: &Path) -> Result<Catalog, Error> {
+        let path = root.join("catalog.json");
+        let entries = match path.exists() {
+            true => serde_json::from_slice(&fs::read(&path)?)?,
+            false => BTreeMap::new(),
+        };
+        Ok(Catalog { path, entries })
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        write_atomic(&self.path, &serde_json::to_vec_pretty(&self.entries)?)
+    }
+
+    /// Records a complete fetch of the feed made on `today`. Products in the feed are added or
+    /// updated and marked seen; products missing from it are marked removed.
+    pub fn merge(&mut self, products: &[Product], today: &str) {
+        for product in products {
+            let entry = self
+                .entries
+                .entry(product.machine_name.clone())
+                .or_insert_with(|| CatalogEntry {
+                    product: product.clone(),
+                    first_seen: today.to_string(),
+                    last_seen: today.to_string(),
+                    removed: false,
+                    removed_on: None,
+                    removals: 0,
+                    removal_dates: Vec::new(),
+                });
+            entry.product = product.clone();
+            entry.last_seen = today.to_string();
+            entry.removed = false;
+            entry.removed_on = None;
+        }
+        let seen: HashSet<&String> = products.iter().map(|p| &p.machine_name).collect();
+        for (name, entry) in self.entries.iter_mut() {
+            if !seen.contains(name) && !entry.removed {
+                entry.removed = true;
+                entry.removed_on = Some(today.to_string());
+                entry.removals += 1;
+                entry.removal_dates.push(today.to_string());
+            }
+        }
+    }
+
+    /// The date of the latest merge, which marked every product it saw as last seen then.
+    pub fn last_merged(&self) -> Option<&str> {
+        self.entries.values().map(|entry| entry.last_seen.as_str()).max()
+    }
+
+    pub fn removed(&self) -> Vec<&CatalogEntry> {
+        self.entries.values().filter(|entry| entry.removed).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_dir;
+
+    const CHUNK: &str = include_str!("../tests/fixtures/www.humblebundle.com/api/v1/trove/chunk_index=0");
+
+    fn products() -> Vec<Product> {
+        serde_json::from_str(CHUNK).unwrap()
+    }
+
+    #[test]
+    fn merge_remove_and_return() {
+        let dir = test_dir("catalog-merge");
+        let all = products();
+        assert!(all.len() >= 2);
+        let gone = all[0].machine_name.clone();
+        let mut catalog = Catalog::load(&dir).unwrap();
+        catalog.merge(&all, "2020-01-01");
+        assert_eq!(catalog.entries.len(), all.len());
+        assert!(catalog.removed().is_empty());
+
+        catalog.merge(&all[1..], "2020-02-01");
+        let entry = &catalog.entries[&gone];
+        assert!(entry.removed);
+        assert_eq!(entry.removed_on.as_deref(), Some("2020-02-01"));
+        assert_eq!(entry.removals, 1);
+        assert_eq!(entry.last_seen, "2020-01-01");
+        assert_eq!(catalog.removed().len(), 1);
+        catalog.merge(&all[1..], "2020-02-15");
+        assert_eq!(catalog.entries[&gone].removals, 1);
+
+        catalog.merge(&all, "2020-03-01");
+        let entry = &catalog.entries[&gone];
+        assert!(!entry.removed);
+        assert_eq!(entry.removed_on, None);
+        assert_eq!(entry.removals, 1);
+        assert_eq!(entry.removal_dates, ["2020-02-01"]);
+        assert_eq!(entry.first_seen, "2020-01-01");
+        assert_eq!(entry.last_seen, "2020-03-01");
+        assert_eq!(catalog.last_merged(), Some("2020-03-01"));
+
+        catalog.save().unwrap();
+        let loaded = Catalog::load(&dir).unwrap();
+        assert_eq!(loaded.entries[&gone].removal_dates, ["2020-02-01"]);
+    }
+}
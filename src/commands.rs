@@ -0,0 +1,619 @@
+/// This module registers the commands of each source with the command line interface. The
+/// commands report failures through `Failure`, which the interface prints and turns into the
+/// exit status when they are run from the command line.
+use crate::cache::{self, Cache, Entry};
+use crate::config::Config;
+use crate::downloader::Downloader;
+use crate::icli::{choose, parse, required, Cmd, Command, Completion, Failure, Group, ICLI};
+use crate::plan;
+use crate::queue;
+use crate::trove::{report_missing, Game, Trove};
+use crate::util::format_size;
+use crate::watch::DownloadWatcher;
+use chrono::{DateTime, Local};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+pub struct Context {
+    /// Built on first use, so that commands which only touch the cache never fetch the feed.
+    trove: Option<Trove>,
+    pub config: Config,
+    pub cache: Cache,
+    pub downloader: Downloader,
+    pub watcher: Option<DownloadWatcher>,
+    /// Machine names of the games in the last listing, which numbers given to commands refer to.
+    pub listing: Vec<String>,
+    /// Whether an ambiguous game can be chosen at a prompt.
+    pub interactive: bool,
+}
+
+impl Context {
+    pub fn new(config: Config, cache: Cache, downloader: Downloader) -> Context {
+        Context {
+            trove: None,
+            config,
+            cache,
+            downloader,
+            watcher: None,
+            listing: Vec::new(),
+            interactive: false,
+        }
+    }
+
+    /// The trove, built from the feed the first time a command needs it.
+    pub fn trove(&mut self) -> Result<&mut Trove, Failure> {
+        Ok(self.parts()?.0)
+    }
+
+    /// The trove along with the cache and downloader its operations take.
+    fn parts(&mut self) -> Result<(&mut Trove, &Cache, &Downloader), Failure> {
+        if self.trove.is_none() {
+            let trove = Trove::new(&self.config, &self.cache).map_err(|err| format!("trove: {}", err))?;
+            report_missing(&trove.missing);
+            self.trove = Some(trove);
+        }
+        let trove = self.trove.as_mut().expect("trove built above");
+        Ok((trove, &self.cache, &self.downloader))
+    }
+
+    /// Updates the download status if the watcher moved installers into the trove.
+    pub fn refresh(&mut self) {
+        if let (Some(watcher), Some(trove)) = (&self.watcher, &mut self.trove) {
+            if watcher.moved.try_iter().count() > 0 {
+                trove.update_download_status();
+            }
+        }
+    }
+
+    /// Finds the game `args` name, by machine name, index in the last listing or words of its
+    /// human name. Several games matching a human name are offered to choose from.
+    fn game(&mut self, args: &[&str]) -> Result<String, Failure> {
+        let query = args.join(" ");
+        if query.is_empty() {
+            return Err(Failure::Usage("missing <game>".to_string()));
+        }
+        if let Ok(index) = query.parse::<usize>() {
+            return match self.listing.get(index) {
+                Some(machine_name) => Ok(machine_name.clone()),
+                None if self.listing.is_empty() => Err(Failure::Usage(format!(
+                    "no game {}, list games first",
+                    index
+                ))),
+                None => Err(Failure::Usage(format!(
+                    "no game {}, the last listing has {}",
+                    index,
+                    self.listing.len()
+                ))),
+            };
+        }
+        let interactive = self.interactive;
+        let trove = &*self.trove()?;
+        if trove.games.contains_key(&query) {
+            return Ok(query);
+        }
+        let wanted = words(&query);
+        let mut matches: Vec<&Game> = trove
+            .games
+            .values()
+            .filter(|game| {
+                let name = words(&game.human_name);
+                wanted.iter().all(|word| name.iter().any(|part| part.starts_with(word)))
+            })
+            .collect();
+        matches.sort_by(|a, b| a.human_name.cmp(&b.human_name));
+        if let Some(exact) = matches.iter().find(|game| words(&game.human_name) == wanted) {
+            return Ok(exact.machine_name.clone());
+        }
+        match matches.len() {
+            0 => Err(Failure::Error(format!("no game matches {}", query))),
+            1 => Ok(matches[0].machine_name.clone()),
+            _ => {
+                let options: Vec<String> = matches.iter().map(|game| trove.format(game)).collect();
+                if !interactive {
+                    return Err(Failure::Error(format!(
+                        "{} matches several games:\n{}",
+                        query,
+                        options.join("\n")
+                    )));
+                }
+                match choose(&options) {
+                    Some(index) => Ok(matches[index].machine_name.clone()),
+                    None => Err(Failure::Error("no game chosen".to_string())),
+                }
+            }
+        }
+    }
+
+    fn online(&self) -> Result<(), Failure> {
+        match self.cache.is_offline() {
+            true => Err(Failure::Error("offline".to_string())),
+            false => Ok(()),
+        }
+    }
+}
+
+type Commands = Vec<Box<dyn Cmd<Context>>>;
+
+/// The top level shell, with the commands of every source.
+pub fn shell() -> ICLI<Context> {
+    ICLI::new(">> ").add(cache()).add(downloader()).add(trove())
+}
+
+fn cache() -> Commands {
+    vec![
+        Group::new("cache", "Inspect and prune the web cache", cache_shell),
+        Command::new(
+            "cache_all_metadata",
+            "",
+            "Cache the feed metadata and images of every game",
+            cache_all_metadata,
+        ),
+        Command::new("cache_thumbnails", "", "Cache the thumbnail of every game", cache_thumbnails),
+        Command::new("cache_screenshots", "", "Cache the screenshots of every game", cache_screenshots),
+    ]
+}
+
+fn cache_shell() -> ICLI<Context> {
+    ICLI::subshell("cache").add(vec![
+        Command::new("ls", "[prefix]", "List cached urls starting with prefix", cache_ls),
+        Command::new("stats", "", "Summarize the size and age of the cache", cache_stats),
+        Command::completing(
+            "prune",
+            "age <days> | size <megabytes> | prefix <url>",
+            "Remove old, least recently used or matching entries",
+            cache_prune,
+            |_| {
+                vec![
+                    Completion::new("age", "<days>"),
+                    Completion::new("size", "<megabytes>"),
+                    Completion::new("prefix", "<url>"),
+                ]
+            },
+        ),
+    ])
+}
+
+fn downloader() -> Commands {
+    vec![
+        Command::completing(
+            "download",
+            "<game> | all",
+            "Download a game, or every missing game",
+            download,
+            |context| {
+                let mut arguments = games(context);
+                arguments.push(Completion::new("all", ""));
+                arguments
+            },
+        ),
+        Command::completing(
+            "queue",
+            "[add <game> | filter <text> | missing | run | clear]",
+            "List, fill or run the download queue",
+            queue,
+            |context| {
+                vec![
+                    Completion {
+                        next: games(context),
+                        ..Completion::new("add", "<game>")
+                    },
+                    Completion::new("filter", "<text>"),
+                    Completion::new("missing", ""),
+                    Completion::new("run", ""),
+                    Completion::new("clear", ""),
+                ]
+            },
+        ),
+        Command::new(
+            "plan",
+            "[gigabytes]",
+            "List the missing installers, most at risk first, that fit in free space or a budget",
+            plan,
+        ),
+        Command::new("verify", "", "Check installers against the feed and redownload bad ones", verify),
+        Command::completing(
+            "torrents",
+            "export [dir] | magnets [file]",
+            "Export torrents or magnet links of the missing installers",
+            torrents,
+            |_| vec![Completion::new("export", "[dir]"), Completion::new("magnets", "[file]")],
+        ),
+    ]
+}
+
+fn trove() -> Commands {
+    vec![
+        Group::new("trove", "List and download games in the trove", trove_shell),
+        Command::new("update", "", "Check which installers are in the trove root", update),
+        Command::new("downloaded", "", "List downloaded games", downloaded),
+        Command::new("not_downloaded", "", "List games still to be downloaded", not_downloaded),
+        Command::new("removed", "", "List games removed from the trove", removed),
+        Command::new("priority", "", "List missing games, most at risk of removal first", priority),
+        Command::new("diff", "[fetches back]", "Compare an earlier fetch of the feed with the latest", diff),
+    ]
+}
+
+fn trove_shell() -> ICLI<Context> {
+    ICLI::subshell("trove").add(vec![
+        Command::completing(
+            "list",
+            "[--missing | --downloaded | --removed]",
+            "List games",
+            trove_list,
+            |_| {
+                vec![
+                    Completion::new("--missing", ""),
+                    Completion::new("--downloaded", ""),
+                    Completion::new("--removed", ""),
+                ]
+            },
+        ),
+        Command::completing(
+            "download",
+            "<game>",
+            "Download the missing installers of a game",
+            trove_download,
+            games,
+        ),
+        Command::new("update", "", "Check which installers are in the trove root", update),
+        Command::new("priority", "", "List missing games, most at risk of removal first", priority),
+        Command::new("diff", "[fetches back]", "Compare an earlier fetch of the feed with the latest", diff),
+    ])
+}
+
+/// The lowercase alphanumeric words of `text`.
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+/// Every game's machine name, which also completes from its human name. Nothing completes
+/// before the trove is built.
+fn games(context: &Context) -> Vec<Completion> {
+    context
+        .trove
+        .iter()
+        .flat_map(|trove| trove.games.values())
+        .map(|game| Completion {
+            display: game.human_name.clone(),
+            ..Completion::new(&game.machine_name, "")
+        })
+        .collect()
+}
+
+fn format_time(time: SystemTime) -> String {
+    DateTime::<Local>::from(time).format("%Y-%m-%d %H:%M").to_string()
+}
+
+/// Prints `games` numbered, in the order given, and returns their machine names as the new
+/// listing.
+fn print_games(trove: &Trove, games: Vec<&Game>) -> Vec<String> {
+    games
+        .iter()
+        .zip(0..)
+        .for_each(|(p, i)| println!("{} {}", i, trove.format(p)));
+    games.iter().map(|game| game.machine_name.clone()).collect()
+}
+
+fn cache_all_metadata(context: &mut Context, _args: &[&str]) -> Result<(), Failure> {
+    let (trove, cache, _) = context.parts()?;
+    trove.cache_all_metadata(cache).map_err(|err| err.to_string())?;
+    Ok(())
+}
+
+fn cache_thumbnails(context: &mut Context, _args: &[&str]) -> Result<(), Failure> {
+    let (trove, cache, _) = context.parts()?;
+    trove.cache_thumbnails(cache);
+    Ok(())
+}
+
+fn cache_screenshots(context: &mut Context, _args: &[&str]) -> Result<(), Failure> {
+    let (trove, cache, _) = context.parts()?;
+    trove.cache_screenshots(cache);
+    Ok(())
+}
+
+fn cache_ls(context: &mut Context, args: &[&str]) -> Result<(), Failure> {
+    let prefix = args.first().cloned().unwrap_or("");
+    let mut entries: Vec<Entry> = context
+        .cache
+        .entries()
+        .filter(|e| e.url.starts_with(prefix))
+        .collect();
+    entries.sort_by(|a, b| a.url.cmp(&b.url));
+    for entry in entries {
+        println!(
+            "{} {:>10} {} v{} {} {}",
+            format_time(entry.fetched),
+            format_size(entry.size),
+            entry.content_type.as_deref().unwrap_or("-"),
+            entry.versions,
+            entry.status.map_or("".to_string(), |code| format!("[{}]", code)),
+            entry.url
+        );
+    }
+    Ok(())
+}
+
+fn cache_stats(context: &mut Context, _args: &[&str]) -> Result<(), Failure> {
+    let stats = context.cache.stats();
+    println!(
+        "Entries: {} ({} negative); Versions: {}",
+        stats.entries, stats.negative, stats.versions
+    );
+    println!(
+        "Size: {}; Versions: {}",
+        format_size(stats.size),
+        format_size(stats.versions_size)
+    );
+    if let (Some(oldest), Some(newest)) = (stats.oldest, stats.newest) {
+        println!("Fetched: {} to {}", format_time(oldest), format_time(newest));
+    }
+    Ok(())
+}
+
+fn cache_prune(context: &mut Context, args: &[&str]) -> Result<(), Failure> {
+    let cache = &context.cache;
+    let pruned = match required(args, 0, "what to prune by")? {
+        "age" => {
+            let days: u64 = parse(required(args, 1, "<days>")?, "days")?;
+            let age = days
+                .checked_mul(86400)
+                .map(Duration::from_secs)
+                .filter(|age| SystemTime::now().checked_sub(*age).is_some())
+                .ok_or_else(|| Failure::Usage(format!("{} days is too long ago", days)))?;
+            cache.prune_older_than(age)
+        }
+        "size" => {
+            let megabytes: u64 = parse(required(args, 1, "<megabytes>")?, "megabytes")?;
+            let budget = megabytes
+                .checked_mul(1024 * 1024)
+                .ok_or_else(|| Failure::Usage(format!("{} megabytes is too large", megabytes)))?;
+            cache.prune_to_size(budget)
+        }
+        "prefix" => cache.prune_prefix(required(args, 1, "<url>")?),
+        other => return Err(Failure::Usage(format!("cannot prune by {}", other))),
+    };
+    let entries: Vec<Entry> = pruned.map_err(|err: cache::CacheError| err.to_string())?;
+    let size: u64 = entries.iter().map(|e| e.size + e.versions_size).sum();
+    println!("Pruned {} entries, {}", entries.len(), format_size(size));
+    Ok(())
+}
+
+fn torrents(context: &mut Context, args: &[&str]) -> Result<(), Failure> {
+    let (trove, cache, _) = context.parts()?;
+    match required(args, 0, "export or magnets")? {
+        "export" => {
+            let dir = match (args.get(1), &trove.torrent_watch) {
+                (Some(dir), _) => PathBuf::from(dir),
+                (None, Some(dir)) => dir.clone(),
+                (None, None) => {
+                    return Err(Failure::Usage("missing dir, and trove.torrent_watch is not set".to_string()))
+                }
+            };
+            let exported = trove
+                .export_torrents(cache, &dir)
+                .map_err(|err| err.to_string())?;
+            println!("Exported {} torrents to {}", exported.len(), dir.display());
+        }
+        "magnets" => {
+            let magnets = trove.magnets(cache);
+            match args.get(1) {
+                Some(file) => {
+                    std::fs::write(file, magnets.join("\n") + "\n").map_err(|err| err.to_string())?;
+                    println!("Wrote {} magnets to {}", magnets.len(), file);
+                }
+                None => magnets.iter().for_each(|magnet| println!("{}", magnet)),
+            }
+        }
+        other => return Err(Failure::Usage(format!("unknown action {}", other))),
+    }
+    Ok(())
+}
+
+/// Runs the queue, failing if any of its items did not download.
+fn run_queue(context: &mut Context) -> Result<(), Failure> {
+    context.online()?;
+    let (trove, _, downloader) = context.parts()?;
+    trove.run_queue(downloader).map_err(|err| err.to_string())?;
+    let failed = trove
+        .queue
+        .items
+        .iter()
+        .filter(|item| match item.status {
+            queue::Status::Failed(_) | queue::Status::Skipped(_) => true,
+            _ => false,
+        })
+        .count();
+    match failed {
+        0 => Ok(()),
+        failed => Err(Failure::Error(format!("{} items did not download", failed))),
+    }
+}
+
+fn queue(context: &mut Context, args: &[&str]) -> Result<(), Failure> {
+    let added = match args.first().cloned() {
+        None => {
+            let items = &context.trove()?.queue.items;
+            items.iter().zip(0..).for_each(|(item, i)| {
+                println!("{} {} {} {}", i, item.machine_name, item.platform, item.status)
+            });
+            context.listing = items.iter().map(|item| item.machine_name.clone()).collect();
+            return Ok(());
+        }
+        Some("add") => {
+            let machine_name = context.game(&args[1..])?;
+            context.trove()?.enqueue(&machine_name)
+        }
+        Some("filter") => context.trove()?.enqueue_matching(&args[1..].join(" ")),
+        Some("missing") => context.trove()?.enqueue_missing(),
+        Some("clear") => {
+            let queue = &mut context.trove()?.queue;
+            queue.clear_done();
+            queue.save().map_err(|err| err.to_string())?;
+            return Ok(());
+        }
+        Some("run") => return run_queue(context),
+        Some(other) => return Err(Failure::Usage(format!("unknown action {}", other))),
+    };
+    println!("Queued {}", added.map_err(|err| err.to_string())?);
+    Ok(())
+}
+
+fn plan(context: &mut Context, args: &[&str]) -> Result<(), Failure> {
+    let budget = match args.first() {
+        Some(gigabytes) => match parse::<f64>(gigabytes, "gigabytes")? {
+            gigabytes if gigabytes.is_finite() && gigabytes >= 0.0 => {
+                Some((gigabytes * 1024.0 * 1024.0 * 1024.0) as u64)
+            }
+            _ => return Err(Failure::Usage(format!("{} is not a usable budget in gigabytes", gigabytes))),
+        },
+        None => None,
+    };
+    let trove = context.trove()?;
+    for dir in [&trove.root, &trove.downloads].iter() {
+        if let Some(free) = plan::free_space(dir) {
+            println!("Free in {}: {}", dir.display(), format_size(free));
+        }
+    }
+    print!("{}", trove.plan(budget));
+    Ok(())
+}
+
+fn download_game(context: &mut Context, machine_name: &str) -> Result<(), Failure> {
+    context.online()?;
+    let (trove, _, downloader) = context.parts()?;
+    trove.download(downloader, machine_name).map_err(|err| err.to_string())?;
+    Ok(())
+}
+
+fn download(context: &mut Context, args: &[&str]) -> Result<(), Failure> {
+    if args == ["all"] {
+        context.online()?;
+        let added = context.trove()?.enqueue_missing().map_err(|err| err.to_string())?;
+        println!("Queued {}", added);
+        return run_queue(context);
+    }
+    let machine_name = context.game(args)?;
+    let trove = context.trove()?;
+    println!("Downloading: {}", trove.format(&trove.games[&machine_name]));
+    download_game(context, &machine_name)
+}
+
+fn verify(context: &mut Context, _args: &[&str]) -> Result<(), Failure> {
+    let (trove, cache, downloader) = context.parts()?;
+    let results = trove.verify().map_err(|err| err.to_string())?;
+    for (name, platform, verification) in results.iter() {
+        println!("{} {} {}", name, platform, verification);
+    }
+    let bad: Vec<(&String, &String)> = results
+        .iter()
+        .filter(|(_, _, v)| v.is_bad())
+        .map(|(name, platform, _)| (name, platform))
+        .collect();
+    if bad.is_empty() {
+        return Ok(());
+    }
+    if cache.is_offline() {
+        return Err(Failure::Error(format!("offline, not redownloading {} files", bad.len())));
+    }
+    let mut failed = 0;
+    for (name, platform) in bad {
+        if let Err(err) = trove.redownload(downloader, name, platform) {
+            println!("verify: {} {}: {}", name, platform, err);
+            failed += 1;
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        failed => Err(Failure::Error(format!("{} files failed to redownload", failed))),
+    }
+}
+
+fn update(context: &mut Context, _args: &[&str]) -> Result<(), Failure> {
+    context.trove()?.update_download_status();
+    Ok(())
+}
+
+fn downloaded(context: &mut Context, _args: &[&str]) -> Result<(), Failure> {
+    let trove = context.trove()?;
+    context.listing = print_games(trove, trove.downloaded());
+    Ok(())
+}
+
+fn not_downloaded(context: &mut Context, _args: &[&str]) -> Result<(), Failure> {
+    let trove = context.trove()?;
+    context.listing = print_games(trove, trove.not_downloaded());
+    Ok(())
+}
+
+fn removed(context: &mut Context, _args: &[&str]) -> Result<(), Failure> {
+    let trove = context.trove()?;
+    context.listing = print_games(trove, trove.removed());
+    Ok(())
+}
+
+fn priority(context: &mut Context, _args: &[&str]) -> Result<(), Failure> {
+    let trove = context.trove()?;
+    match trove.until_next_addition() {
+        Some(until) if until < chrono::Duration::zero() => {
+            println!("Next addition is past, the trove page is out of date")
+        }
+        Some(until) => println!("Next addition in {} days {} hours", until.num_days(), until.num_hours() % 24),
+        None => {}
+    }
+    let priority = trove.priority();
+    priority.iter().zip(0..).for_each(|(risk, i)| {
+        println!(
+            "{} {}{} {} days{}",
+            i,
+            if risk.at_risk { "! " } else { "" },
+            trove.format(risk.game),
+            risk.age,
+            match risk.removals {
+                0 => "".to_string(),
+                n => format!(", rotated out {} times", n),
+            }
+        )
+    });
+    context.listing = priority.iter().map(|risk| risk.game.machine_name.clone()).collect();
+    Ok(())
+}
+
+fn diff(context: &mut Context, args: &[&str]) -> Result<(), Failure> {
+    let back = match args.first() {
+        Some(back) => parse(back, "number of fetches")?,
+        None => 1,
+    };
+    let (trove, cache, _) = context.parts()?;
+    let diff = trove.diff(cache, back).map_err(|err| err.to_string())?;
+    match diff.is_empty() {
+        true => println!("No changes."),
+        false => print!("{}", diff),
+    }
+    Ok(())
+}
+
+fn trove_list(context: &mut Context, args: &[&str]) -> Result<(), Failure> {
+    let trove = context.trove()?;
+    let games = match args.first().cloned() {
+        None => {
+            let mut games: Vec<&Game> = trove.games.values().collect();
+            games.sort_by(|a, b| a.human_name.cmp(&b.human_name));
+            games
+        }
+        Some("--missing") => trove.not_downloaded(),
+        Some("--downloaded") => trove.downloaded(),
+        Some("--removed") => trove.removed(),
+        Some(other) => return Err(Failure::Usage(format!("unknown filter {}", other))),
+    };
+    context.listing = print_games(trove, games);
+    Ok(())
+}
+
+fn trove_download(context: &mut Context, args: &[&str]) -> Result<(), Failure> {
+    let machine_name = context.game(args)?;
+    download_game(context, &machine_name)
+}
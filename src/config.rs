@@ -1,34 +1,499 @@
 extern crate toml;
 
 use serde::Deserialize;
-use std::fs::File;
-use std::io::Read;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::env;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use toml::Value;
+
+/// Prefix of the environment variables that override keys, e.g. `TARNISH_TROVE_ROOT` for `root`
+/// in `[trove]`.
+const ENV_PREFIX: &str = "TARNISH_";
+const SECTIONS: [&str; 3] = ["trove", "system", "cache"];
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// None of the places searched has a config file.
+    NotFound(Vec<PathBuf>),
+    Io(PathBuf, io::Error),
+    /// The file is not valid toml, or a value does not fit its key.
+    Parse {
+        path: PathBuf,
+        line: Option<usize>,
+        message: String,
+    },
+    MissingSection(&'static str),
+    UnknownKey {
+        path: PathBuf,
+        key: String,
+        line: Option<usize>,
+    },
+    /// An override names no key, or its value does not fit the key.
+    Environment { variable: String, message: String },
+    MissingDirectory { key: &'static str, path: PathBuf },
+    /// A value parses but is out of range for its key.
+    InvalidValue { key: String, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::NotFound(searched) => {
+                write!(f, "no config file, looked for:")?;
+                for path in searched {
+                    write!(f, "\n  {}", path.display())?;
+                }
+                Ok(())
+            }
+            ConfigError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            ConfigError::Parse { path, line, message } => match line {
+                Some(line) => write!(f, "{}:{}: {}", path.display(), line, message),
+                None => write!(f, "{}: {}", path.display(), message),
+            },
+            ConfigError::MissingSection(section) => write!(f, "missing section [{}]", section),
+            ConfigError::UnknownKey { path, key, line } => match line {
+                Some(line) => write!(f, "{}:{}: unknown key {}", path.display(), line, key),
+                None => write!(f, "{}: unknown key {}", path.display(), key),
+            },
+            ConfigError::Environment { variable, message } => write!(f, "{}: {}", variable, message),
+            ConfigError::MissingDirectory { key, path } => {
+                write!(f, "{} does not exist: {}", key, path.display())
+            }
+            ConfigError::InvalidValue { key, message } => write!(f, "{}: {}", key, message),
+        }
+    }
+}
+
+impl error::Error for ConfigError {}
 
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Trove {
     pub root: PathBuf,
+    /// Base url that relative installer urls from the feed are resolved against.
+    pub download_base: Option<String>,
+    /// Platforms whose installers are tracked and downloaded.
+    #[serde(default = "default_platforms")]
+    pub platforms: Vec<String>,
+    /// Watch folder of a torrent client; `torrents export` writes there by default.
+    pub torrent_watch: Option<PathBuf>,
+    /// Number of installers the download queue fetches at once.
+    #[serde(default = "default_download_concurrency")]
+    pub download_concurrency: usize,
+}
+
+fn default_download_concurrency() -> usize {
+    2
+}
+
+fn default_platforms() -> Vec<String> {
+    vec!["windows".to_string()]
 }
 
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct System {
     pub downloads: PathBuf,
     pub cache: PathBuf,
 }
 
+/// Overrides the default time to live for urls containing `pattern`.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    pub pattern: String,
+    /// Seconds until an entry is refetched. Entries without one never expire.
+    pub ttl: Option<u64>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Cache {
+    /// Default seconds until an entry is refetched. Entries never expire without one.
+    pub ttl: Option<u64>,
+    /// Seconds until a url that answered 404 or 410 is asked again, at most the url's ttl.
+    #[serde(default = "default_negative_ttl")]
+    pub negative_ttl: u64,
+    #[serde(default)]
+    pub policies: Vec<Policy>,
+    /// Never touch the network; urls that are not cached fail with `CacheError::NotCached`.
+    #[serde(default)]
+    pub offline: bool,
+    /// Requests per second allowed to each host. Unlimited without one.
+    pub requests_per_second: Option<f64>,
+    /// Overrides `requests_per_second` for individual hosts.
+    #[serde(default)]
+    pub host_requests_per_second: HashMap<String, f64>,
+    /// Times a request answered with 429 or 5xx is retried.
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    /// Milliseconds before the first retry, doubling with each attempt.
+    #[serde(default = "default_backoff")]
+    pub backoff: u64,
+    /// Longest wait in milliseconds before a retry, including one asked for by `Retry-After`.
+    #[serde(default = "default_max_backoff")]
+    pub max_backoff: u64,
+    /// Number of urls fetched in parallel by bulk operations.
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+}
+
+fn default_concurrency() -> usize {
+    4
+}
+
+fn default_negative_ttl() -> u64 {
+    86400
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+fn default_backoff() -> u64 {
+    500
+}
+
+fn default_max_backoff() -> u64 {
+    60_000
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Cache {
+            ttl: None,
+            negative_ttl: default_negative_ttl(),
+            policies: Vec::new(),
+            offline: false,
+            requests_per_second: None,
+            host_requests_per_second: HashMap::new(),
+            retries: default_retries(),
+            backoff: default_backoff(),
+            max_backoff: default_max_backoff(),
+            concurrency: default_concurrency(),
+        }
+    }
+}
+
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub trove: Trove,
     pub system: System,
+    #[serde(default)]
+    pub cache: Cache,
 }
 
 impl Config {
-    pub fn new(path: &str) -> Config {
-        let mut contents = String::new();
-        File::open(path)
-            .unwrap()
-            .read_to_string(&mut contents)
-            .unwrap();
-        return toml::from_str(contents.as_str()).unwrap();
+    /// Loads the config file given by `--config`, or else the first of `$TARNISH_CONFIG`,
+    /// `$XDG_CONFIG_HOME/tarnish/config.toml` and `./config.toml` that exists.
+    pub fn load(flag: Option<&Path>) -> Result<Config, ConfigError> {
+        if let Some(path) = flag {
+            return Config::from_file(path);
+        }
+        if let Some(path) = env::var_os("TARNISH_CONFIG") {
+            return Config::from_file(Path::new(&path));
+        }
+        let mut searched = Vec::new();
+        let xdg = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+        if let Some(xdg) = xdg {
+            searched.push(xdg.join("tarnish").join("config.toml"));
+        }
+        searched.push(PathBuf::from("./config.toml"));
+        match searched.iter().find(|path| path.exists()) {
+            Some(path) => Config::from_file(path),
+            None => Err(ConfigError::NotFound(searched)),
+        }
+    }
+
+    /// Reads `path`, applies the environment's overrides and validates the result.
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+        let mut value: Value = toml::from_str(&contents).map_err(|err| parse_error(path, &contents, err))?;
+        let overridden = override_from_env(&mut value)?;
+        for section in ["trove", "system"].iter() {
+            if value.get(section).is_none() {
+                return Err(ConfigError::MissingSection(section));
+            }
+        }
+        let config: Config = match value.try_into() {
+            Ok(config) => config,
+            // Parsing the file again locates the error, unless an override caused it.
+            Err(err) => {
+                let message = err.to_string();
+                return Err(match toml::from_str::<Config>(&contents) {
+                    Err(err) => parse_error(path, &contents, err),
+                    Ok(_) => ConfigError::Environment {
+                        variable: error_key(&message)
+                            .map(|key| format!("{}{}", ENV_PREFIX, key.replace('.', "_").to_uppercase()))
+                            .filter(|variable| overridden.contains(variable))
+                            .unwrap_or_else(|| overridden.join(", ")),
+                        message,
+                    },
+                })
+            }
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let mut directories = vec![
+            ("trove.root", &self.trove.root),
+            ("system.downloads", &self.system.downloads),
+        ];
+        if let Some(watch) = &self.trove.torrent_watch {
+            directories.push(("trove.torrent_watch", watch));
+        }
+        for (key, path) in directories {
+            if !path.is_dir() {
+                return Err(ConfigError::MissingDirectory {
+                    key,
+                    path: path.clone(),
+                });
+            }
+        }
+        let mut rates: Vec<(String, f64)> = self
+            .cache
+            .host_requests_per_second
+            .iter()
+            .map(|(host, rate)| (format!("cache.host_requests_per_second.\"{}\"", host), *rate))
+            .collect();
+        if let Some(rate) = self.cache.requests_per_second {
+            rates.push(("cache.requests_per_second".to_string(), rate));
+        }
+        for (key, rate) in rates {
+            // The cache waits 1 / rate seconds between requests, which has to fit a Duration.
+            let valid = rate.is_finite() && rate > 0.0 && Duration::try_from_secs_f64(1.0 / rate).is_ok();
+            if !valid {
+                return Err(ConfigError::InvalidValue {
+                    key,
+                    message: format!("{} is not a usable number of requests per second", rate),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The key toml names at the end of an error message, as in "... for key `cache.retries`".
+fn error_key(message: &str) -> Option<&str> {
+    let start = message.rfind(" for key `")? + " for key `".len();
+    message[start..].split('`').next()
+}
+
+/// The line `key` is set on in `[section]`, counting from one.
+fn key_line(contents: &str, section: Option<&str>, key: &str) -> Option<usize> {
+    let mut current = None;
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            current = Some(line.trim_matches(|c| c == '[' || c == ']').trim());
+        } else if current == section
+            && line.starts_with(key)
+            && line[key.len()..].trim_start().starts_with('=')
+        {
+            return Some(number + 1);
+        }
+    }
+    None
+}
+
+fn parse_error(path: &Path, contents: &str, err: toml::de::Error) -> ConfigError {
+    let line = err.line_col().map(|(line, _)| line + 1);
+    let message = err.to_string();
+    let message = match message.find(" at line ") {
+        Some(end) => message[..end].to_string(),
+        None => message,
+    };
+    // serde reports "unknown field `key`, expected ..." and toml appends the enclosing table,
+    // locating the error at the end of the table rather than at the key.
+    let field = message
+        .strip_prefix("unknown field `")
+        .and_then(|rest| rest.split('`').next());
+    match field {
+        Some(field) => {
+            let section = error_key(&message);
+            ConfigError::UnknownKey {
+                path: path.to_path_buf(),
+                key: section.map_or(field.to_string(), |section| format!("{}.{}", section, field)),
+                line: key_line(contents, section, field).or(line),
+            }
+        }
+        None => ConfigError::Parse {
+            path: path.to_path_buf(),
+            line,
+            message,
+        },
+    }
+}
+
+/// Sets the keys named by `TARNISH_<SECTION>_<KEY>` variables, returning the variables used.
+/// Other `TARNISH_` variables are ignored; unknown keys of a section fail deserialization.
+/// Values are read as toml where they parse, e.g. `4` or `["windows", "linux"]`, and as
+/// strings otherwise.
+fn override_from_env(value: &mut Value) -> Result<Vec<String>, ConfigError> {
+    let mut overridden = Vec::new();
+    for (variable, raw) in env::vars() {
+        let name = match variable.strip_prefix(ENV_PREFIX) {
+            Some(name) if name != "CONFIG" => name.to_lowercase(),
+            _ => continue,
+        };
+        let section = SECTIONS.iter().find(|section| name.starts_with(&format!("{}_", section)));
+        let (section, key) = match section {
+            Some(section) => (*section, &name[section.len() + 1..]),
+            // Possibly meant for something else, e.g. a wrapper script's own settings, but more
+            // likely a typo, so it is reported on stderr rather than through the logger.
+            None => {
+                eprintln!(
+                    "Warning: {} names none of [{}], ignoring it",
+                    variable,
+                    SECTIONS.join("], [")
+                );
+                continue;
+            }
+        };
+        let parsed = toml::from_str::<Value>(&format!("value = {}", raw))
+            .ok()
+            .and_then(|table| table.get("value").cloned())
+            .unwrap_or(Value::String(raw));
+        let table = match value {
+            Value::Table(table) => table
+                .entry(section)
+                .or_insert_with(|| Value::Table(Default::default())),
+            _ => unreachable!("a toml document is a table"),
+        };
+        match table {
+            Value::Table(table) => {
+                table.insert(key.to_string(), parsed);
+            }
+            _ => {
+                return Err(ConfigError::Environment {
+                    variable,
+                    message: format!("{} is not a section", section),
+                })
+            }
+        }
+        overridden.push(variable);
+    }
+    Ok(overridden)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_dir;
+    use std::sync::Mutex;
+
+    /// Held by tests that read or set `TARNISH_` variables, which every test process shares.
+    static ENV: Mutex<()> = Mutex::new(());
+
+    /// Writes a config whose `[system]` and `[trove]` point at existing directories, followed
+    /// by `extra`, which starts on line 8.
+    fn write_config(name: &str, extra: &str) -> PathBuf {
+        let dir = test_dir(name);
+        fs::create_dir_all(dir.join("root")).unwrap();
+        fs::create_dir_all(dir.join("downloads")).unwrap();
+        let path = dir.join("config.toml");
+        let contents = format!(
+            "[system]\ndownloads = {:?}\ncache = {:?}\n\n[trove]\nroot = {:?}\n\n{}",
+            dir.join("downloads"),
+            dir.join("cache"),
+            dir.join("root"),
+            extra
+        );
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn load(path: &Path) -> ConfigError {
+        let _env = ENV.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        Config::from_file(path).err().expect("config should not load")
+    }
+
+    #[test]
+    fn loads_valid_config() {
+        let path = write_config("config-valid", "[cache]\nretries = 5\n");
+        let _env = ENV.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(config.cache.retries, 5);
+        assert_eq!(config.trove.platforms, ["windows"]);
+    }
+
+    #[test]
+    fn unknown_key_names_its_line() {
+        let path = write_config("config-unknown", "[cache]\nretries = 5\nretires = 3\nbackoff = 10\n");
+        match load(&path) {
+            ConfigError::UnknownKey { key, line, .. } => {
+                assert_eq!(key, "cache.retires");
+                assert_eq!(line, Some(10));
+            }
+            err => panic!("expected an unknown key, got {}", err),
+        }
+    }
+
+    #[test]
+    fn missing_section() {
+        let dir = test_dir("config-section");
+        let path = dir.join("config.toml");
+        fs::write(&path, format!("[trove]\nroot = {:?}\n", dir)).unwrap();
+        match load(&path) {
+            ConfigError::MissingSection(section) => assert_eq!(section, "system"),
+            err => panic!("expected a missing section, got {}", err),
+        }
+    }
+
+    #[test]
+    fn bad_value_type_names_its_line() {
+        let path = write_config("config-type", "[cache]\nbackoff = 10\nretries = \"three\"\n");
+        match load(&path) {
+            ConfigError::Parse { line, message, .. } => {
+                assert_eq!(line, Some(10), "{}", message);
+                assert!(message.contains("cache.retries"), "{}", message);
+            }
+            err => panic!("expected a parse error, got {}", err),
+        }
+    }
+
+    #[test]
+    fn unusable_rates() {
+        for (extra, key) in [
+            ("[cache]\nrequests_per_second = nan\n", "cache.requests_per_second"),
+            ("[cache]\nrequests_per_second = 0.0\n", "cache.requests_per_second"),
+            ("[cache]\nrequests_per_second = 1e-20\n", "cache.requests_per_second"),
+            (
+                "[cache.host_requests_per_second]\n\"hb.imgix.net\" = -inf\n",
+                "cache.host_requests_per_second.\"hb.imgix.net\"",
+            ),
+        ]
+        .iter()
+        {
+            match load(&write_config("config-rate", extra)) {
+                ConfigError::InvalidValue { key: invalid, .. } => assert_eq!(invalid, *key),
+                err => panic!("expected an invalid rate, got {}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn bad_env_value_names_its_variable() {
+        let path = write_config("config-env", "[cache]\nretries = 5\n");
+        let _env = ENV.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        env::set_var("TARNISH_CACHE_RETRIES", "three");
+        env::set_var("TARNISH_TROVEROOT", "/nowhere");
+        let result = Config::from_file(&path);
+        env::remove_var("TARNISH_CACHE_RETRIES");
+        env::remove_var("TARNISH_TROVEROOT");
+        match result.err().expect("config should not load") {
+            ConfigError::Environment { variable, .. } => assert_eq!(variable, "TARNISH_CACHE_RETRIES"),
+            err => panic!("expected an environment error, got {}", err),
+        }
     }
 }
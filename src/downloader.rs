@@ -0,0 +1,138 @@
+/// This module streams large files, such as game installers, straight to disk. Unlike the
+/// cache, bodies are never held in memory. Data is written to `<name>.part` and renamed once
+/// complete; an existing `.part` file is resumed with an HTTP Range request.
+use crate::cache::CacheError;
+use crate::util::format_size;
+use reqwest::header::{CONTENT_LENGTH, RANGE};
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const BAR_WIDTH: usize = 40;
+
+fn part_path(dest: &Path) -> PathBuf {
+    let mut part = dest.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+/// Draws a single line progress bar, overwriting the previous one.
+fn print_progress(name: &str, done: u64, total: Option<u64>) {
+    match total {
+        Some(total) if total > 0 => {
+            let filled = (done * BAR_WIDTH as u64 / total) as usize;
+            print!(
+                "\r{} [{}{}] {:>3}% {} / {}",
+                name,
+                "=".repeat(filled.min(BAR_WIDTH)),
+                " ".repeat(BAR_WIDTH - filled.min(BAR_WIDTH)),
+                done * 100 / total,
+                format_size(done),
+                format_size(total)
+            );
+        }
+        _ => print!("\r{} {}", name, format_size(done)),
+    }
+    let _ = std::io::stdout().flush();
+}
+
+pub struct Downloader {
+    client: reqwest::Client,
+}
+
+impl Downloader {
+    pub fn new() -> Downloader {
+        Downloader {
+            client: reqwest::Client::builder()
+                .timeout(None)
+                .build()
+                .unwrap_or_else(|_| reqwest::Client::new()),
+        }
+    }
+
+    /// Downloads `url` to `dest`, resuming a previous partial download if there is one.
+    /// Returns the size of the completed file.
+    pub fn download(&self, url: &str, dest: &Path) -> Result<u64, CacheError> {
+        self.transfer(url, dest, true)
+    }
+
+    /// Like `download`, but without drawing a progress bar, for use from several threads.
+    pub fn download_quietly(&self, url: &str, dest: &Path) -> Result<u64, CacheError> {
+        self.transfer(url, dest, false)
+    }
+
+    /// Returns the number of bytes already downloaded to `dest`'s part file.
+    pub fn resumable(dest: &Path) -> u64 {
+        fs::metadata(part_path(dest)).map(|m| m.len()).unwrap_or(0)
+    }
+
+    fn transfer(&self, url: &str, dest: &Path, progress: bool) -> Result<u64, CacheError> {
+        url::Url::parse(url)?;
+        let part = part_path(dest);
+        let offset = Downloader::resumable(dest);
+        let mut request = self.client.get(url);
+        if offset > 0 {
+            request = request.header(RANGE, format!("bytes={}-", offset).as_str());
+        }
+        let mut resp = request.send()?;
+        let status = resp.status().as_u16();
+        let (mut file, mut done) = match status {
+            206 => (OpenOptions::new().append(true).open(&part)?, offset),
+            // The part file already holds everything there is.
+            416 if offset > 0 => {
+                fs::rename(&part, dest)?;
+                return Ok(offset);
+            }
+            200..=299 => (fs::File::create(&part)?, 0),
+            code => {
+                return Err(CacheError::Status {
+                    code,
+                    url: url.to_string(),
+                })
+            }
+        };
+        let total = resp
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(|length| length + done);
+        let name = dest
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let mut buffer = vec![0; 64 * 1024];
+        let mut drawn = Instant::now();
+        if progress {
+            print_progress(&name, done, total);
+        }
+        loop {
+            let read = resp.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            file.write_all(&buffer[..read])?;
+            done += read as u64;
+            if progress && drawn.elapsed() > Duration::from_millis(200) {
+                print_progress(&name, done, total);
+                drawn = Instant::now();
+            }
+        }
+        if progress {
+            print_progress(&name, done, total);
+            println!();
+        }
+        file.sync_all()?;
+        if let Some(total) = total {
+            if done < total {
+                return Err(CacheError::Io(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("{} of {} bytes received: {}", done, total, url),
+                )));
+            }
+        }
+        fs::rename(&part, dest)?;
+        Ok(done)
+    }
+}
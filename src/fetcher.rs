@@ -0,0 +1,123 @@
+/// This module provides the means by which the cache retrieves urls. `HttpFetcher` talks to
+/// the network; `FileFetcher` answers from local files so that the cache and the trove can be
+/// exercised against recorded copies of the humble bundle pages.
+use crate::cache::CacheError;
+use reqwest::header::{CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RETRY_AFTER};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+
+pub struct Request<'a> {
+    pub url: &'a str,
+    pub etag: Option<&'a str>,
+    pub last_modified: Option<&'a str>,
+}
+
+pub struct Response {
+    pub status: u16,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub content_type: Option<String>,
+    /// Seconds the server asked us to wait before retrying.
+    pub retry_after: Option<u64>,
+    pub body: Vec<u8>,
+}
+
+pub trait Fetcher: Send + Sync {
+    /// Performs `request`. Unsuccessful statuses are returned as responses, not errors.
+    fn fetch(&self, request: &Request) -> Result<Response, CacheError>;
+}
+
+pub struct HttpFetcher {
+    client: reqwest::Client,
+}
+
+impl HttpFetcher {
+    pub fn new() -> HttpFetcher {
+        HttpFetcher {
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+impl Fetcher for HttpFetcher {
+    fn fetch(&self, request: &Request) -> Result<Response, CacheError> {
+        let mut builder = self.client.get(request.url);
+        if let Some(etag) = request.etag {
+            builder = builder.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = request.last_modified {
+            builder = builder.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        let mut resp = builder.send()?;
+        let header = |name| {
+            resp.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string())
+        };
+        let mut response = Response {
+            status: resp.status().as_u16(),
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            content_type: header(CONTENT_TYPE),
+            retry_after: header(RETRY_AFTER).and_then(|value| value.parse().ok()),
+            body: Vec::new(),
+        };
+        resp.read_to_end(&mut response.body)?;
+        Ok(response)
+    }
+}
+
+/// Answers requests from files. Urls registered with `insert` map to the given file, all
+/// others to `<root>/<host>/<path>` with any query appended to the file name as `_<query>`
+/// (e.g. `trove/chunk_index=0`). Urls without a file answer 404.
+pub struct FileFetcher {
+    root: PathBuf,
+    files: HashMap<String, PathBuf>,
+}
+
+impl FileFetcher {
+    pub fn new<T: Into<PathBuf>>(root: T) -> FileFetcher {
+        FileFetcher {
+            root: root.into(),
+            files: HashMap::new(),
+        }
+    }
+
+    pub fn insert<T: Into<PathBuf>>(&mut self, url: &str, file: T) {
+        self.files.insert(url.to_string(), file.into());
+    }
+
+    pub fn path(&self, url: &str) -> Result<PathBuf, CacheError> {
+        if let Some(file) = self.files.get(url) {
+            return Ok(file.clone());
+        }
+        let parsed = url::Url::parse(url)?;
+        let mut name = parsed.path().trim_start_matches('/').to_string();
+        if let Some(query) = parsed.query() {
+            name.push('_');
+            name.push_str(&query.replace('&', "_"));
+        }
+        Ok(self.root.join(parsed.host_str().unwrap_or_default()).join(name))
+    }
+}
+
+impl Fetcher for FileFetcher {
+    fn fetch(&self, request: &Request) -> Result<Response, CacheError> {
+        let path = self.path(request.url)?;
+        let (status, body) = match path.is_file() {
+            true => (200, fs::read(&path)?),
+            false => (404, Vec::new()),
+        };
+        Ok(Response {
+            status,
+            etag: None,
+            last_modified: None,
+            content_type: None,
+            retry_after: None,
+            body,
+        })
+    }
+}
@@ -0,0 +1,457 @@
+/// This module is a small framework for interactive command line interfaces. Each source of
+/// games registers its commands with an `ICLI`; a command may open a subshell with commands of
+/// its own, e.g. `trove` switches to a `trove>` prompt until `exit`. The same commands can be
+/// run once, without a prompt, from the command line. At the prompt, commands and their
+/// arguments complete with tab and the usage of a command is hinted as it is typed.
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::Editor;
+use std::borrow::Cow;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// Why a command failed.
+pub enum Failure {
+    /// The arguments do not match the command's usage.
+    Usage(String),
+    Error(String),
+}
+
+impl From<String> for Failure {
+    fn from(message: String) -> Failure {
+        Failure::Error(message)
+    }
+}
+
+/// Parses the argument `arg`, described as `what` if it is invalid.
+pub fn parse<T: FromStr>(arg: &str, what: &str) -> Result<T, Failure>
+where
+    T::Err: Display,
+{
+    arg.parse()
+        .map_err(|err| Failure::Usage(format!("invalid {} '{}': {}", what, arg, err)))
+}
+
+/// Returns the argument at `index`, failing if it is missing.
+pub fn required<'a>(args: &[&'a str], index: usize, what: &str) -> Result<&'a str, Failure> {
+    args.get(index)
+        .cloned()
+        .ok_or_else(|| Failure::Usage(format!("missing {}", what)))
+}
+
+/// A word that completes a command or argument, with the words that may follow it.
+pub struct Completion {
+    pub word: String,
+    /// Shown in the list of candidates, e.g. a game's human name.
+    pub display: String,
+    /// Hinted once the word has been typed.
+    pub usage: String,
+    pub next: Vec<Completion>,
+}
+
+impl Completion {
+    pub fn new(word: &str, usage: &str) -> Completion {
+        Completion {
+            word: word.to_string(),
+            display: word.to_string(),
+            usage: usage.to_string(),
+            next: Vec::new(),
+        }
+    }
+
+    /// Whether typing `prefix` could lead to this word, by the word itself or its display.
+    fn matches(&self, prefix: &str) -> bool {
+        self.word.starts_with(prefix)
+            || self.display.to_lowercase().starts_with(&prefix.to_lowercase())
+    }
+}
+
+/// Follows `words` down from `completions`, returning what may follow the last of them.
+fn follow<'a>(completions: &'a [Completion], words: &[&str]) -> Option<&'a Completion> {
+    let (first, rest) = words.split_first()?;
+    let completion = completions.iter().find(|completion| completion.word == *first)?;
+    match rest.is_empty() {
+        true => Some(completion),
+        false => follow(&completion.next, rest),
+    }
+}
+
+/// Completes and hints the line being edited from the completions of the current shell.
+#[derive(Default)]
+pub struct Helper {
+    completions: Vec<Completion>,
+}
+
+impl Completer for Helper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &rustyline::Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        let start = line.rfind(char::is_whitespace).map_or(0, |index| index + 1);
+        let words: Vec<&str> = line[..start].split_ascii_whitespace().collect();
+        let level = match words.is_empty() {
+            true => &self.completions,
+            false => match follow(&self.completions, &words) {
+                Some(completion) => &completion.next,
+                None => return Ok((start, Vec::new())),
+            },
+        };
+        let prefix = &line[start..];
+        let candidates = level
+            .iter()
+            .filter(|completion| completion.matches(prefix))
+            .map(|completion| Pair {
+                display: match completion.display == completion.word {
+                    true => completion.word.clone(),
+                    false => format!("{} ({})", completion.display, completion.word),
+                },
+                replacement: completion.word.clone() + " ",
+            })
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for Helper {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &rustyline::Context<'_>) -> Option<String> {
+        if pos < line.len() {
+            return None;
+        }
+        let words: Vec<&str> = line.split_ascii_whitespace().collect();
+        let usage = &follow(&self.completions, &words)?.usage;
+        match (usage.is_empty(), line.ends_with(' ')) {
+            (true, _) => None,
+            (false, true) => Some(usage.clone()),
+            (false, false) => Some(format!(" {}", usage)),
+        }
+    }
+}
+
+impl Highlighter for Helper {
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("\x1b[2m{}\x1b[0m", hint))
+    }
+}
+
+impl Validator for Helper {}
+
+impl rustyline::Helper for Helper {}
+
+/// Asks which of `options` is meant, returning its index, or `None` if the answer is not one.
+pub fn choose(options: &[String]) -> Option<usize> {
+    for (i, option) in options.iter().enumerate() {
+        println!("{} {}", i, option);
+    }
+    print!("Which one? ");
+    io::stdout().flush().ok()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).ok()?;
+    answer.trim().parse().ok().filter(|i| *i < options.len())
+}
+
+pub enum CResult<C> {
+    Ok,
+    /// The message describing the failure, already prefixed with the command.
+    Err(String),
+    /// Like `Err`, for words that do not match a command's usage.
+    Usage(String),
+    Subshell(ICLI<C>),
+}
+
+pub trait Cmd<C> {
+    fn name(&self) -> &str;
+    /// The arguments the command takes, e.g. `<machine_name> [platform]`.
+    fn usage(&self) -> &str {
+        ""
+    }
+    fn help(&self) -> &str;
+    fn execute(&self, context: &mut C, args: &[&str]) -> Result<(), Failure>;
+    /// The arguments the command can complete.
+    fn arguments(&self, _context: &C) -> Vec<Completion> {
+        Vec::new()
+    }
+    /// Commands that group others return the shell holding them. Run without arguments they
+    /// open it as a subshell; otherwise the arguments are run in it.
+    fn subshell(&self) -> Option<ICLI<C>> {
+        None
+    }
+}
+
+/// A command implemented by a function.
+pub struct Command<C> {
+    name: &'static str,
+    usage: &'static str,
+    help: &'static str,
+    run: fn(&mut C, &[&str]) -> Result<(), Failure>,
+    arguments: Option<fn(&C) -> Vec<Completion>>,
+}
+
+impl<C: 'static> Command<C> {
+    pub fn new(
+        name: &'static str,
+        usage: &'static str,
+        help: &'static str,
+        run: fn(&mut C, &[&str]) -> Result<(), Failure>,
+    ) -> Box<dyn Cmd<C>> {
+        Box::new(Command {
+            name,
+            usage,
+            help,
+            run,
+            arguments: None,
+        })
+    }
+
+    /// A command whose arguments complete from `arguments`.
+    pub fn completing(
+        name: &'static str,
+        usage: &'static str,
+        help: &'static str,
+        run: fn(&mut C, &[&str]) -> Result<(), Failure>,
+        arguments: fn(&C) -> Vec<Completion>,
+    ) -> Box<dyn Cmd<C>> {
+        Box::new(Command {
+            name,
+            usage,
+            help,
+            run,
+            arguments: Some(arguments),
+        })
+    }
+}
+
+impl<C> Cmd<C> for Command<C> {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn usage(&self) -> &str {
+        self.usage
+    }
+
+    fn help(&self) -> &str {
+        self.help
+    }
+
+    fn execute(&self, context: &mut C, args: &[&str]) -> Result<(), Failure> {
+        (self.run)(context, args)
+    }
+
+    fn arguments(&self, context: &C) -> Vec<Completion> {
+        self.arguments.map_or_else(Vec::new, |arguments| arguments(context))
+    }
+}
+
+/// A command that opens a subshell.
+pub struct Group<C> {
+    name: &'static str,
+    help: &'static str,
+    shell: fn() -> ICLI<C>,
+}
+
+impl<C: 'static> Group<C> {
+    pub fn new(name: &'static str, help: &'static str, shell: fn() -> ICLI<C>) -> Box<dyn Cmd<C>> {
+        Box::new(Group { name, help, shell })
+    }
+}
+
+impl<C> Cmd<C> for Group<C> {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn usage(&self) -> &str {
+        "[command]"
+    }
+
+    fn help(&self) -> &str {
+        self.help
+    }
+
+    fn execute(&self, _context: &mut C, _args: &[&str]) -> Result<(), Failure> {
+        Ok(())
+    }
+
+    fn arguments(&self, context: &C) -> Vec<Completion> {
+        (self.shell)().completions(context)
+    }
+
+    fn subshell(&self) -> Option<ICLI<C>> {
+        Some((self.shell)())
+    }
+}
+
+pub struct ICLI<C> {
+    prompt: String,
+    /// The names of the enclosing groups, followed by a space, to prefix messages with.
+    path: String,
+    commands: Vec<Box<dyn Cmd<C>>>,
+}
+
+impl<C> ICLI<C> {
+    pub fn new(prompt: &str) -> ICLI<C> {
+        ICLI {
+            prompt: prompt.to_string(),
+            path: String::new(),
+            commands: Vec::new(),
+        }
+    }
+
+    /// The shell of the group `name`, prompting with `name> `.
+    pub fn subshell(name: &str) -> ICLI<C> {
+        ICLI {
+            prompt: format!("{}> ", name),
+            path: format!("{} ", name),
+            commands: Vec::new(),
+        }
+    }
+
+    pub fn add(mut self, commands: Vec<Box<dyn Cmd<C>>>) -> ICLI<C> {
+        self.commands.extend(commands);
+        self
+    }
+
+    pub fn prompt(&self) -> &str {
+        &self.prompt
+    }
+
+    /// The commands of this shell, including the built in ones, and what completes their
+    /// arguments.
+    pub fn completions(&self, context: &C) -> Vec<Completion> {
+        let mut completions: Vec<Completion> = self
+            .commands
+            .iter()
+            .map(|command| Completion {
+                next: command.arguments(context),
+                ..Completion::new(command.name(), command.usage())
+            })
+            .collect();
+        let mut help = Completion::new("help", "[command]");
+        help.next = self
+            .commands
+            .iter()
+            .map(|command| Completion::new(command.name(), ""))
+            .collect();
+        completions.push(help);
+        completions.push(Completion::new("exit", ""));
+        completions
+    }
+
+    fn find(&self, name: &str) -> Option<&dyn Cmd<C>> {
+        self.commands
+            .iter()
+            .find(|command| command.name() == name)
+            .map(|command| command.as_ref())
+    }
+
+    fn help(&self, args: &[&str]) -> CResult<C> {
+        match args.first() {
+            Some(name) => match self.find(name) {
+                Some(command) => {
+                    println!("{}{} {}", self.path, command.name(), command.usage());
+                    println!("    {}", command.help());
+                }
+                None => return CResult::Usage(format!("{}help: unknown command {}", self.path, name)),
+            },
+            None => {
+                for command in self.commands.iter() {
+                    println!("{:<20} {}", command.name(), command.help());
+                }
+                println!("{:<20} {}", "help", "Describe the commands, or the usage of one");
+                println!("{:<20} {}", "exit", "Leave this shell");
+            }
+        }
+        CResult::Ok
+    }
+
+    /// Runs the command named by the first word of `words` with the rest as its arguments.
+    pub fn execute(&self, context: &mut C, words: &[&str]) -> CResult<C> {
+        let (name, args) = match words.split_first() {
+            Some((name, args)) => (*name, args),
+            None => return CResult::Ok,
+        };
+        if name == "help" {
+            return self.help(args);
+        }
+        let command = match self.find(name) {
+            Some(command) => command,
+            None => {
+                return CResult::Usage(format!(
+                    "{}{}: unknown command, try help",
+                    self.path, name
+                ))
+            }
+        };
+        if let Some(shell) = command.subshell() {
+            return match args.is_empty() {
+                true => CResult::Subshell(shell),
+                false => shell.execute(context, args),
+            };
+        }
+        match command.execute(context, args) {
+            Ok(()) => CResult::Ok,
+            Err(Failure::Error(message)) => CResult::Err(format!("{}{}: {}", self.path, name, message)),
+            Err(Failure::Usage(message)) => CResult::Usage(format!(
+                "{}{}: {}\nusage: {}{} {}",
+                self.path,
+                name,
+                message,
+                self.path,
+                name,
+                command.usage()
+            )),
+        }
+    }
+
+    /// Prompts for commands until `exit` leaves the outermost shell. `before` runs ahead of
+    /// every command.
+    pub fn run<F: FnMut(&mut C)>(self, context: &mut C, history: &str, mut before: F) {
+        let mut rl = Editor::<Helper>::new();
+        rl.set_helper(Some(Helper::default()));
+        if rl.load_history(history).is_err() {
+            println!("No previous history.");
+        }
+        let mut shells = vec![self];
+        while let Some(shell) = shells.last() {
+            if let Some(helper) = rl.helper_mut() {
+                helper.completions = shell.completions(context);
+            }
+            let line = match rl.readline(shell.prompt()) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => {
+                    println!("CTRL-C");
+                    break;
+                }
+                Err(ReadlineError::Eof) => {
+                    println!("CTRL-D");
+                    break;
+                }
+                Err(err) => {
+                    println!("Error: {:?}", err);
+                    break;
+                }
+            };
+            rl.add_history_entry(line.as_str());
+            before(context);
+            let words: Vec<&str> = line.split_ascii_whitespace().collect();
+            if words == ["exit"] {
+                shells.pop();
+                continue;
+            }
+            match shell.execute(context, &words) {
+                CResult::Ok => {}
+                CResult::Err(message) | CResult::Usage(message) => println!("{}", message),
+                CResult::Subshell(subshell) => shells.push(subshell),
+            }
+        }
+        if let Err(err) = rl.save_history(history) {
+            println!("Error saving history: {}", err);
+        }
+    }
+}
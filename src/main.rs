@@ -1,3 +1,4 @@
+extern crate chrono;
 extern crate hex;
 extern crate log;
 extern crate reqwest;
@@ -8,134 +9,112 @@ extern crate serde_json;
 extern crate simple_logger;
 
 mod cache;
+mod catalog;
+mod commands;
 mod config;
+mod downloader;
+mod fetcher;
+mod icli;
 mod library;
+mod plan;
+mod queue;
+mod torrent;
 mod trove;
 mod trove_feed;
 mod util;
+mod verify;
+mod watch;
 
 //use std::str;
 //use std::fs::{self};//, DirEntry};
 use config::Config;
-use trove::Trove;
-use std::process::Command;
-use rustyline::error::ReadlineError;
-use rustyline::Editor;
-use crate::cache::{Cache};
+use downloader::Downloader;
+use crate::cache::Cache;
+use crate::commands::Context;
+use crate::icli::{CResult, Failure};
+use std::path::PathBuf;
 
-
-/*
- * Sketch of API
- * use icli::{ICLI, Cmd};
- *
- * struct ICLI {
- *   history: Vec<String>,
- *   prompt: String,
- * }
- *
- * enum CResult {
- *   Subshell(icli),
- *   Ok(String),
- *   Err(error),
- * }
- *
- * trait Cmd {
- *  fn name() -> String;
- *  fn execute(Vec<&str> args) -> CResult;
- * }
- *
- * let cli = ICLI::new()
- *  .add(trove.cmds)
- *  .add(steam.cmds)
- *  .add(monthly.cmds)
- *  .add(downloader.cmds);
- * cli.run();
- */
+/// Exit status of a command that failed.
+const FAILURE: i32 = 1;
+/// Exit status of flags or a command line that do not match the usage.
+const USAGE: i32 = 2;
 
 fn main() {
     simple_logger::init_with_level(log::Level::Error).unwrap();
-    let config = Config::new("./config.toml");
-    let cache = Cache::new(&config.system.cache);
-    let mut trove = match Trove::new(&config, &cache) {
-        Ok(unwrapped) => unwrapped,
-        Err(error) => panic!("Error constructing trove: {}", error),
-    };
-    let stray = trove.stray_downloads();
-    println!("In downloads: {}", stray.len());
-    trove.move_downloads();
-    trove.update_download_status();
-    let mut rl = rustyline::Editor::<()>::new();
-
-    // `()` can be used when no completer is required
-    let mut rl = Editor::<()>::new();
-    if rl.load_history(".tarnish-history").is_err() {
-        println!("No previous history.");
-    }
-    loop {
-        let readline = rl.readline(">> ");
-        match readline {
-            Ok(line) => {
-                let mut words = line.split_ascii_whitespace();
-                match words.next() {
-                    Some("cache_all_metadata") => {
-                        if let Err(err) = trove.cache_all_metadata(&cache) {
-                            println!("cache_all_metadata: {}", err);
-                        }
-                    }
-                    Some("cache_thumbnails") => {
-                        trove.cache_thumbnails(&cache);
-                    }
-                    Some("cache_screenshots") => {
-                        trove.cache_screenshots(&cache);
-                    }
-                    Some("update") => {
-                        trove.update_download_status();
-                    }
-                    Some("download") => {
-                        let number: usize = words.next().unwrap().parse::<usize>().unwrap();
-                        let game = trove.not_downloaded()[number];
-                        println!("Downloading: {}", trove.format(game));
-                        let chrome = Command::new(r"C:\Program Files (x86)\Google\Chrome\Application\chrome.exe")
-                            .arg(trove.root.join(&game.downloads["windows"]))
-                            .status()
-                            .expect("Failed to launch Chrome.");
-                    }
-                    Some("downloaded") => {
-                        trove
-                            .downloaded()
-                            .iter()
-                            .zip(0..)
-                            .for_each(|(p, i)| println!("{} {}", i, trove.format(p)));
-                    }
-                    Some("not_downloaded") => {
-                        trove
-                            .not_downloaded()
-                            .iter()
-                            .zip(0..)
-                            .for_each(|(p, i)| println!("{} {}", i, trove.format(p)));
-                    }
-                    Some("exit") => break,
-                    Some(_) => {}
-                    None => break,
+    // Flags come first; anything after them is a command to run instead of the REPL.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut offline = false;
+    let mut config_path = None;
+    let mut flags = 0;
+    while let Some(flag) = args.get(flags).filter(|arg| arg.starts_with("--")) {
+        match flag.as_str() {
+            "--offline" => offline = true,
+            "--config" => {
+                flags += 1;
+                config_path = args.get(flags).map(PathBuf::from);
+                if config_path.is_none() {
+                    eprintln!("--config: missing path");
+                    std::process::exit(USAGE);
                 }
-                rl.add_history_entry(line.as_str());
-                println!("Line: {}", line);
             }
-            Err(ReadlineError::Interrupted) => {
-                println!("CTRL-C");
-                break;
+            flag => {
+                eprintln!("unknown flag: {}", flag);
+                std::process::exit(USAGE);
             }
-            Err(ReadlineError::Eof) => {
-                println!("CTRL-D");
-                break;
+        }
+        flags += 1;
+    }
+    let mut config = match Config::load(config_path.as_deref()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("config: {}", err);
+            std::process::exit(FAILURE);
+        }
+    };
+    if offline {
+        config.cache.offline = true;
+    }
+    let cache = Cache::new(&config.system.cache, &config.cache);
+    let mut context = Context::new(config, cache, Downloader::new());
+    let mut shell = commands::shell();
+    if args.len() > flags {
+        // The trove is only built if the command needs it.
+        let words: Vec<&str> = args[flags..].iter().map(|arg| arg.as_str()).collect();
+        match shell.execute(&mut context, &words) {
+            CResult::Ok => return,
+            CResult::Err(message) => {
+                eprintln!("{}", message);
+                std::process::exit(FAILURE);
             }
-            Err(err) => {
-                println!("Error: {:?}", err);
-                break;
+            CResult::Usage(message) => {
+                eprintln!("{}", message);
+                std::process::exit(USAGE);
             }
+            // A group without a command opens its shell.
+            CResult::Subshell(subshell) => shell = subshell,
         }
     }
-    rl.save_history(".tarnish-history").unwrap();
+    context.interactive = true;
+    let trove = match context.trove() {
+        Ok(trove) => trove,
+        Err(Failure::Error(message)) | Err(Failure::Usage(message)) => {
+            eprintln!("{}", message);
+            std::process::exit(FAILURE);
+        }
+    };
+    println!("In downloads: {}", trove.stray_downloads().len());
+    trove.move_downloads();
+    trove.update_download_status();
+    println!("Downloaded: {}; Total: {}", trove.number_downloaded, trove.total);
+    let watcher = match trove.watch_downloads() {
+        Ok(watcher) => Some(watcher),
+        Err(err) => {
+            println!("Not watching {}: {}", trove.downloads.display(), err);
+            None
+        }
+    };
+    context.watcher = watcher;
+    shell.run(&mut context, ".tarnish-history", Context::refresh);
     //let data: Map<String, Value> = serde_json::from_str(data.as_str()).unwrap();
     //data.keys().for_each(|k| println!("{}", k));
     //println!("{}", data.has_key("displayItemData"));
@@ -0,0 +1,104 @@
+/// This module works out whether a set of installers fits on disk before downloading them.
+/// Installers are considered in the order given, normally the removal priority, and each is
+/// marked as fitting while the running total stays within the budget.
+use crate::trove::Game;
+use crate::util::format_size;
+use std::fmt;
+use std::path::Path;
+
+pub struct Planned<'a> {
+    pub game: &'a Game,
+    pub platform: String,
+    /// Bytes still to be downloaded, excluding any partial download.
+    pub size: u64,
+    pub fits: bool,
+}
+
+pub struct Plan<'a> {
+    pub items: Vec<Planned<'a>>,
+    pub budget: u64,
+    /// Total of the installers that fit.
+    pub planned: u64,
+    /// Total of all installers.
+    pub total: u64,
+}
+
+impl<'a> Plan<'a> {
+    pub fn new(candidates: Vec<(&'a Game, String, u64)>, budget: u64) -> Plan<'a> {
+        let mut planned = 0;
+        let mut total = 0;
+        let items = candidates
+            .into_iter()
+            .map(|(game, platform, size)| {
+                total += size;
+                let fits = planned + size <= budget;
+                if fits {
+                    planned += size;
+                }
+                Planned {
+                    game,
+                    platform,
+                    size,
+                    fits,
+                }
+            })
+            .collect();
+        Plan {
+            items,
+            budget,
+            planned,
+            total,
+        }
+    }
+
+    pub fn fits(&self) -> bool {
+        self.total <= self.budget
+    }
+
+    /// Whether not even one of the installers fits.
+    pub fn nothing_fits(&self) -> bool {
+        !self.items.is_empty() && self.items.iter().all(|item| !item.fits)
+    }
+}
+
+impl<'a> fmt::Display for Plan<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut running = 0;
+        for item in self.items.iter().filter(|item| item.fits) {
+            running += item.size;
+            writeln!(
+                f,
+                "{:>10} {:>10} {} {}",
+                format_size(item.size),
+                format_size(running),
+                item.game.human_name,
+                item.platform
+            )?;
+        }
+        let left_out: Vec<&Planned> = self.items.iter().filter(|item| !item.fits).collect();
+        if !left_out.is_empty() {
+            writeln!(f, "Does not fit:")?;
+            for item in left_out {
+                writeln!(
+                    f,
+                    "{:>10} {} {}",
+                    format_size(item.size),
+                    item.game.human_name,
+                    item.platform
+                )?;
+            }
+        }
+        writeln!(
+            f,
+            "Planned {} of {}; budget {}",
+            format_size(self.planned),
+            format_size(self.total),
+            format_size(self.budget)
+        )
+    }
+}
+
+/// Free space on the file system holding `path`, or `None` if it cannot be determined.
+pub fn free_space(path: &Path) -> Option<u64> {
+    fs2::available_space(path).ok()
+}
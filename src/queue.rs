@@ -0,0 +1,103 @@
+/// This module holds the batch download queue. The queue is stored as `queue.json` in the
+/// trove root after every change so that a batch survives restarts; items that were in
+/// progress when tarnish exited are picked up again, resuming their `.part` files.
+use crate::util::write_atomic;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Status {
+    Pending,
+    Downloading,
+    Done,
+    Failed(String),
+    /// Not attempted, e.g. because there was not enough free space.
+    Skipped(String),
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Status::Pending => write!(f, "pending"),
+            Status::Downloading => write!(f, "downloading"),
+            Status::Done => write!(f, "done"),
+            Status::Failed(reason) => write!(f, "failed: {}", reason),
+            Status::Skipped(reason) => write!(f, "skipped: {}", reason),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Item {
+    pub machine_name: String,
+    pub platform: String,
+    pub status: Status,
+}
+
+pub struct Queue {
+    path: PathBuf,
+    pub items: Vec<Item>,
+}
+
+impl Queue {
+    /// Loads `queue.json` from `root`. Items left downloading by a previous run are pending again.
+    pub fn load(root: &Path) -> Result<Queue, Error> {
+        let path = root.join("queue.json");
+        let mut items: Vec<Item> = match path.exists() {
+            true => serde_json::from_slice(&fs::read(&path)?)?,
+            false => Vec::new(),
+        };
+        for item in items.iter_mut() {
+            if item.status == Status::Downloading {
+                item.status = Status::Pending;
+            }
+        }
+        Ok(Queue { path, items })
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        write_atomic(&self.path, &serde_json::to_vec_pretty(&self.items)?)
+    }
+
+    /// Adds an installer unless it is already queued and not finished. Failed and skipped
+    /// items are retried. Returns whether anything changed.
+    pub fn enqueue(&mut self, machine_name: &str, platform: &str) -> bool {
+        let existing = self
+            .items
+            .iter_mut()
+            .find(|item| item.machine_name == machine_name && item.platform == platform);
+        match existing {
+            Some(item) if item.status == Status::Pending || item.status == Status::Downloading => false,
+            Some(item) => {
+                item.status = Status::Pending;
+                true
+            }
+            None => {
+                self.items.push(Item {
+                    machine_name: machine_name.to_string(),
+                    platform: platform.to_string(),
+                    status: Status::Pending,
+                });
+                true
+            }
+        }
+    }
+
+    /// Drops finished items from the queue.
+    pub fn clear_done(&mut self) {
+        self.items.retain(|item| item.status != Status::Done);
+    }
+
+    /// Returns the index of the next pending item and marks it downloading.
+    pub fn start_next(&mut self) -> Option<usize> {
+        let index = self
+            .items
+            .iter()
+            .position(|item| item.status == Status::Pending)?;
+        self.items[index].status = Status::Downloading;
+        Some(index)
+    }
+}
@@ -0,0 +1,127 @@
+/// This module reads just enough of a bencoded .torrent file to turn it into a magnet link:
+/// the SHA-1 of the raw `info` dictionary and the torrent's name. Torrents come from the
+/// network, so malformed input yields `None` and nesting is limited to `MAX_DEPTH`.
+extern crate sha1;
+
+use sha1::Digest;
+
+/// Deepest nesting of lists and dictionaries accepted, far beyond any real torrent.
+const MAX_DEPTH: usize = 64;
+
+/// Parses the byte string at `pos`, returning it and the index following it.
+fn parse_bytes(data: &[u8], pos: usize) -> Option<(&[u8], usize)> {
+    let colon = pos + data[pos..].iter().position(|b| *b == b':')?;
+    let length: usize = std::str::from_utf8(&data[pos..colon]).ok()?.parse().ok()?;
+    let start = colon + 1;
+    let end = start.checked_add(length)?;
+    Some((data.get(start..end)?, end))
+}
+
+/// Returns the index following the value that starts at `pos`, nested `depth` deep.
+fn skip(data: &[u8], pos: usize, depth: usize) -> Option<usize> {
+    match data.get(pos)? {
+        b'i' => Some(pos + data[pos..].iter().position(|b| *b == b'e')? + 1),
+        b'l' | b'd' if depth < MAX_DEPTH => {
+            let mut pos = pos + 1;
+            while *data.get(pos)? != b'e' {
+                pos = skip(data, pos, depth + 1)?;
+            }
+            Some(pos + 1)
+        }
+        b'0'..=b'9' => parse_bytes(data, pos).map(|(_, end)| end),
+        _ => None,
+    }
+}
+
+/// Returns the raw value of each key of the dictionary at `pos`.
+fn dictionary(data: &[u8], pos: usize) -> Option<Vec<(&[u8], &[u8])>> {
+    if *data.get(pos)? != b'd' {
+        return None;
+    }
+    let mut entries = Vec::new();
+    let mut pos = pos + 1;
+    while *data.get(pos)? != b'e' {
+        let (key, value) = parse_bytes(data, pos)?;
+        let end = skip(data, value, 1)?;
+        entries.push((key, &data[value..end]));
+        pos = end;
+    }
+    Some(entries)
+}
+
+fn info(torrent: &[u8]) -> Option<&[u8]> {
+    dictionary(torrent, 0)?
+        .into_iter()
+        .find(|(key, _)| *key == b"info")
+        .map(|(_, value)| value)
+}
+
+/// The hex encoded SHA-1 of the torrent's `info` dictionary.
+pub fn info_hash(torrent: &[u8]) -> Option<String> {
+    let mut hasher = sha1::Sha1::new();
+    hasher.input(info(torrent)?);
+    Some(hex::encode(&hasher.result()))
+}
+
+pub fn magnet(torrent: &[u8]) -> Option<String> {
+    let hash = info_hash(torrent)?;
+    let info = info(torrent)?;
+    let name = dictionary(info, 0)?
+        .into_iter()
+        .find(|(key, _)| *key == b"name")
+        .and_then(|(_, value)| parse_bytes(value, 0))
+        .map(|(name, _)| url::form_urlencoded::byte_serialize(name).collect::<String>());
+    Some(match name {
+        Some(name) => format!("magnet:?xt=urn:btih:{}&dn={}", hash, name),
+        None => format!("magnet:?xt=urn:btih:{}", hash),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TORRENT: &[u8] = b"d8:announce26:http://tracker.example/ann4:infod6:lengthi1024e\
+        4:name12:Game One.exe12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaaee";
+
+    #[test]
+    fn info_hash_of_known_torrent() {
+        assert_eq!(
+            info_hash(TORRENT).as_deref(),
+            Some("2440c77bf2f6da7d1dfcaa5f5c034d08cc127256")
+        );
+    }
+
+    #[test]
+    fn magnet_of_known_torrent() {
+        assert_eq!(
+            magnet(TORRENT).as_deref(),
+            Some("magnet:?xt=urn:btih:2440c77bf2f6da7d1dfcaa5f5c034d08cc127256&dn=Game+One.exe")
+        );
+    }
+
+    #[test]
+    fn truncated_torrent() {
+        for end in 0..TORRENT.len() {
+            assert_eq!(magnet(&TORRENT[..end]), None, "truncated at {}", end);
+        }
+    }
+
+    #[test]
+    fn invalid_torrent() {
+        assert_eq!(info_hash(b""), None);
+        assert_eq!(info_hash(b"le"), None);
+        assert_eq!(info_hash(b"d4:infoxe"), None);
+        assert_eq!(info_hash(b"d4:info99999999999999999999:e"), None);
+        assert_eq!(info_hash(b"d4:infoi12"), None);
+    }
+
+    #[test]
+    fn deeply_nested_torrent() {
+        let mut torrent = b"d4:info".to_vec();
+        torrent.extend(std::iter::repeat(b'l').take(100_000));
+        torrent.extend(std::iter::repeat(b'e').take(100_000));
+        torrent.push(b'e');
+        assert_eq!(info_hash(&torrent), None);
+    }
+}
@@ -4,20 +4,32 @@
 
 /// The responsibilities of Trove end once the games have been installed.
 
-use crate::cache::Cache;
+use crate::cache::{Cache, CacheError, Progress, Version};
+use crate::catalog::Catalog;
 use crate::config::Config;
-use crate::trove_feed::{Feed, Product};
-use crate::util::{extension, url_path_ext};
+use crate::downloader::Downloader;
+use crate::plan::{free_space, Plan};
+use crate::queue::{Queue, Status};
+use crate::torrent;
+use crate::verify::{Verification, Verifier};
+use crate::watch::{DownloadWatcher, Expected};
+use crate::trove_feed::{self, Feed, FeedDiff, Product};
+use crate::util::{extension, format_size, url_path_ext};
 use std::collections::HashMap;
 //use serde_json::Value::{Array, Object};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
 use log::warn;
 use select::document::Document;
 use select::predicate::Attr; //, Class, Name, Predicate, Element};
+use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::ops::{Deref, DerefMut};
 use std::io::{Error, ErrorKind};
 use std::str;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(Debug)]
 pub struct Game {
@@ -25,11 +37,15 @@ pub struct Game {
     pub human_name: String,
     pub description: String,
     pub date_added: u32,
-    pub downloaded: bool, // eventually HashMap
+    /// Whether the installer of each platform the game is offered on is in the trove root.
+    pub downloaded: HashMap<String, bool>,
     pub installed: bool,
     pub executable: PathBuf,
     pub download_urls: HashMap<String, String>,
+    pub torrent_urls: HashMap<String, String>,
     pub downloads: HashMap<String, PathBuf>,
+    pub file_sizes: HashMap<String, u64>,
+    pub md5s: HashMap<String, String>,
     pub logo: Option<String>,
     pub image: String,
     pub screenshots: Vec<String>,
@@ -39,6 +55,51 @@ pub struct Game {
     pub removed_from_trove: bool,
 }
 
+impl Game {
+    /// The platforms in `platforms` that this game is offered on.
+    pub fn tracked<'a>(&self, platforms: &'a [String]) -> Vec<&'a String> {
+        platforms
+            .iter()
+            .filter(|platform| self.downloads.contains_key(*platform))
+            .collect()
+    }
+
+    /// Whether the installers of all tracked platforms have been downloaded. Games not offered
+    /// on any tracked platform count as neither downloaded nor not downloaded.
+    pub fn is_downloaded(&self, platforms: &[String]) -> Option<bool> {
+        let tracked = self.tracked(platforms);
+        if tracked.is_empty() {
+            return None;
+        }
+        Some(
+            tracked
+                .iter()
+                .all(|platform| self.downloaded.get(*platform) == Some(&true)),
+        )
+    }
+
+    /// The tracked platforms whose installers have not been downloaded yet.
+    pub fn missing_platforms(&self, platforms: &[String]) -> Vec<String> {
+        self.tracked(platforms)
+            .into_iter()
+            .filter(|platform| self.downloaded.get(*platform) != Some(&true))
+            .cloned()
+            .collect()
+    }
+}
+
+/// A not yet downloaded game with its estimated risk of being pulled from the trove.
+pub struct Risk<'a> {
+    pub game: &'a Game,
+    /// Days since the game was added to the trove.
+    pub age: i64,
+    /// Times the game has rotated out of the trove before.
+    pub removals: u32,
+    /// Whether the game will be older at the next addition than the youngest game that has
+    /// been removed.
+    pub at_risk: bool,
+}
+
 pub struct Games(HashMap<String, Game>);
 
 impl Deref for Games {
@@ -57,37 +118,253 @@ impl DerefMut for Games {
 
 pub struct Trove {
     pub feed: Feed,
+    /// When the trove page, which the feed is read from, was last fetched.
+    pub feed_fetched: SystemTime,
     pub cache: PathBuf,
     pub downloads: PathBuf,
     pub root: PathBuf,
+    pub download_base: Option<String>,
+    /// Platforms whose installers are tracked, in the feed's download platform order.
+    pub platforms: Vec<String>,
+    /// Watch folder of a torrent client that exported torrents are written to by default.
+    pub torrent_watch: Option<PathBuf>,
     pub number_downloaded: u32,
     pub total: u32,
     pub games: Games,
+    /// Urls that were needed but are not in the cache while offline.
+    pub missing: Vec<String>,
+    /// Every product ever seen in the feed, including those since removed.
+    pub catalog: Catalog,
+    pub queue: Queue,
+    pub download_concurrency: usize,
     //pub installed_games: Vec<String>,
     //pub downloaded_games: Vec<String>,
     //pub not_downloaded_games: Vec<String>,
 }
 
-fn get_page(cache: &Cache, index: u8) -> Result<Vec<Product>, Error> {
-    let bytes = cache.retrieve(
-        format!(
-            "https://www.humblebundle.com/api/v1/trove/chunk?index={}",
-            index
-        )
-        .as_str(),
-    )?;
-    let chunk: Vec<Product> = serde_json::from_str(str::from_utf8(&bytes).unwrap())?;
+/// Why an operation on the trove failed.
+#[derive(Debug)]
+pub enum TroveError {
+    Cache(CacheError),
+    Io(Error),
+    /// A chunk page is not the json the feed is expected to be.
+    Feed(serde_json::Error),
+    UnknownGame(String),
+    NoInstaller { machine_name: String, platform: String },
+    /// An installer url is relative and `trove.download_base` is not set.
+    NoDownloadBase(String),
+    InvalidUrl(url::ParseError),
+    NotEnoughSpace(PathBuf),
+    /// A diff asked for more earlier fetches of the feed than the cache preserved.
+    NoEarlierFetch { available: usize },
+}
+
+impl fmt::Display for TroveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TroveError::Cache(err) => write!(f, "{}", err),
+            TroveError::Io(err) => write!(f, "io error: {}", err),
+            TroveError::Feed(err) => write!(f, "malformed feed: {}", err),
+            TroveError::UnknownGame(name) => write!(f, "unknown game: {}", name),
+            TroveError::NoInstaller { machine_name, platform } => {
+                write!(f, "{} has no {} installer", machine_name, platform)
+            }
+            TroveError::NoDownloadBase(url) => {
+                write!(f, "{} is relative, set trove.download_base in config to resolve it", url)
+            }
+            TroveError::InvalidUrl(err) => write!(f, "invalid url: {}", err),
+            TroveError::NotEnoughSpace(root) => write!(f, "not enough space in {}", root.display()),
+            TroveError::NoEarlierFetch { available: 0 } => write!(f, "no earlier fetch of the feed"),
+            TroveError::NoEarlierFetch { available: 1 } => write!(f, "only 1 earlier fetch of the feed"),
+            TroveError::NoEarlierFetch { available } => {
+                write!(f, "only {} earlier fetches of the feed", available)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TroveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TroveError::Cache(err) => Some(err),
+            TroveError::Io(err) => Some(err),
+            TroveError::Feed(err) => Some(err),
+            TroveError::InvalidUrl(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<CacheError> for TroveError {
+    fn from(err: CacheError) -> Self {
+        TroveError::Cache(err)
+    }
+}
+
+impl From<Error> for TroveError {
+    fn from(err: Error) -> Self {
+        TroveError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for TroveError {
+    fn from(err: serde_json::Error) -> Self {
+        TroveError::Feed(err)
+    }
+}
+
+impl From<url::ParseError> for TroveError {
+    fn from(err: url::ParseError) -> Self {
+        TroveError::InvalidUrl(err)
+    }
+}
+
+impl From<TroveError> for Error {
+    fn from(err: TroveError) -> Self {
+        match err {
+            TroveError::Io(err) => err,
+            TroveError::Cache(err) => err.into(),
+            err => Error::new(ErrorKind::Other, err),
+        }
+    }
+}
+
+/// Chunk pages are read until one comes back empty; a feed with no empty page among this
+/// many is rejected rather than read forever.
+const MAX_PAGES: u8 = 100;
+const TROVE_URL: &str = "https://www.humblebundle.com/monthly/trove";
+/// Longest time between retrieving the first and the last chunk page of one fetch.
+const FETCH_WINDOW: Duration = Duration::from_secs(60);
+/// How close the next addition has to be for games at risk to be ranked first.
+const ADDITION_SOON_DAYS: i64 = 3;
+
+fn chunk_url(index: u8) -> String {
+    format!(
+        "https://www.humblebundle.com/api/v1/trove/chunk?index={}",
+        index
+    )
+}
+
+fn get_page(cache: &Cache, index: u8) -> Result<Vec<Product>, TroveError> {
+    let bytes = cache.retrieve(chunk_url(index).as_str())?;
+    let chunk: Vec<Product> = serde_json::from_slice(&bytes)?;
     Ok(chunk)
 }
 
-fn get_products(cache: &Cache) -> Result<Vec<Product>, Error> {
+/// Returns the time of each earlier fetch of the feed that changed a chunk page,
+/// newest first. A version is named by the last fetch that still returned it, and the pages
+/// of one fetch are retrieved within `FETCH_WINDOW` of each other, so each fetch is the
+/// earliest of a run of version times no further apart than that.
+fn previous_fetches(versions: &[Vec<Version>]) -> Vec<SystemTime> {
+    let mut times: Vec<SystemTime> = versions.iter().flatten().map(|version| version.fetched).collect();
+    times.sort();
+    let mut fetches: Vec<SystemTime> = Vec::new();
+    let mut last: Option<SystemTime> = None;
+    for time in times {
+        let apart = last.map_or(true, |last| time.duration_since(last).map_or(true, |gap| gap > FETCH_WINDOW));
+        if apart {
+            fetches.push(time);
+        }
+        last = Some(time);
+    }
+    fetches.reverse();
+    fetches
+}
+
+/// Returns the products of the chunk pages as they were `back` fetches ago, or as currently
+/// cached when `back` is 0. Nothing is fetched. The pages are those cached from the first
+/// onwards, up to the empty page that ended the newest fetch. Only fetches that changed some page count, as
+/// unchanged pages keep no version. At the chosen fetch each page uses its oldest version
+/// fetched at or after it, or its current copy if none was.
+pub fn get_previous_products(cache: &Cache, back: usize) -> Result<Vec<Product>, TroveError> {
+    let urls: Vec<String> = (0..MAX_PAGES)
+        .map(chunk_url)
+        .take_while(|url| cache.fetched_at(url).is_ok())
+        .collect();
+    let versions: Vec<Vec<Version>> = urls.iter().map(|url| cache.get_versions(url)).collect();
+    let at = match back {
+        0 => None,
+        back => {
+            let fetches = previous_fetches(&versions);
+            match fetches.get(back - 1) {
+                Some(at) => Some(*at),
+                None => return Err(TroveError::NoEarlierFetch { available: fetches.len() }),
+            }
+        }
+    };
     let mut products = Vec::new();
-    for i in 0..5 {
-        products.extend_from_slice(&get_page(cache, i)?);
+    for (url, versions) in urls.iter().zip(versions) {
+        let version = at.and_then(|at| versions.into_iter().find(|version| version.fetched >= at));
+        let bytes = match version {
+            Some(version) => fs::read(&version.path)?,
+            None => cache.retrieve_cached(url)?,
+        };
+        let chunk: Vec<Product> = serde_json::from_slice(&bytes)?;
+        products.extend(chunk);
     }
     Ok(products)
 }
 
+/// Returns the products of every chunk page, with the urls of the pages read. The feed does
+/// not say how many pages there are, so pages are read until one is empty. A page that is not
+/// cached while offline ends the feed early and its url is added to `missing`.
+fn get_products(cache: &Cache, missing: &mut Vec<String>) -> Result<(Vec<Product>, Vec<String>), TroveError> {
+    let mut products = Vec::new();
+    let mut pages = Vec::new();
+    for i in 0..MAX_PAGES {
+        let page = match get_page(cache, i) {
+            Ok(page) => page,
+            Err(TroveError::Cache(CacheError::NotCached(url))) => {
+                missing.push(url);
+                return Ok((products, pages));
+            }
+            Err(err) => return Err(err),
+        };
+        pages.push(chunk_url(i));
+        if page.is_empty() {
+            return Ok((products, pages));
+        }
+        products.extend(page);
+    }
+    Err(TroveError::Io(Error::new(
+        ErrorKind::InvalidData,
+        format!("none of the first {} chunk pages is empty", MAX_PAGES),
+    )))
+}
+
+/// Lists the urls that were needed but not cached on stderr, apart from a command's output.
+pub fn report_missing(missing: &[String]) {
+    if !missing.is_empty() {
+        eprintln!("Not cached: {}", missing.len());
+        missing.iter().for_each(|url| eprintln!("  {}", url));
+    }
+}
+
+/// Reads the feed embedded in the trove page.
+fn parse_feed(text: &[u8]) -> Result<Feed, Error> {
+    let text = str::from_utf8(text).map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+    let doc = Document::from(text);
+    let data = doc
+        .find(Attr("id", "webpack-monthly-trove-data"))
+        .next()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("no trove data in {}", TROVE_URL)))?
+        .text();
+    Ok(serde_json::from_str(data.as_str())?)
+}
+
+fn report(progress: Progress) {
+    println!(
+        "Cached {}/{}, {}; {} failed",
+        progress.completed - progress.failures.len(),
+        progress.total,
+        format_size(progress.bytes),
+        progress.failures.len()
+    );
+    for (url, err) in progress.failures.iter() {
+        println!("Warning: {}: {}", url, err);
+    }
+}
+
 /*
  * trait Into<T>: Sized {fn into(self) -> T;}
  * trait From<T>: Sized {fn from(T) -> Self;}
@@ -95,17 +372,39 @@ fn get_products(cache: &Cache) -> Result<Vec<Product>, Error> {
 impl From<&Product> for Game {
     fn from(p: &Product) -> Game {
         let mut download_urls = HashMap::<String, String>::new();
-        download_urls.insert("windows".to_string(), p.downloads["windows"].url.web.clone());
+        let mut torrent_urls = HashMap::<String, String>::new();
+        let mut downloads = HashMap::<String, PathBuf>::new();
+        let mut file_sizes = HashMap::<String, u64>::new();
+        let mut md5s = HashMap::<String, String>::new();
+        for (platform, download) in p.downloads.iter() {
+            let file_name = match PathBuf::from(&download.url.web).file_name() {
+                Some(file_name) => PathBuf::from(file_name),
+                None => {
+                    warn!("{} has no {} installer", p.machine_name, platform);
+                    continue;
+                }
+            };
+            download_urls.insert(platform.clone(), download.url.web.clone());
+            if let Some(bittorrent) = &download.url.bittorrent {
+                torrent_urls.insert(platform.clone(), bittorrent.clone());
+            }
+            downloads.insert(platform.clone(), file_name);
+            file_sizes.insert(platform.clone(), download.file_size);
+            md5s.insert(platform.clone(), download.md5.clone());
+        }
         Game {
             machine_name: p.machine_name.clone(),
             human_name: p.human_name.clone(),
             description: p.description_text.clone(),
             date_added: p.date_added,
-            downloaded: false,
+            downloaded: HashMap::new(),
             installed: false,
             executable: "".to_string().into(),
-            downloads: download_urls.iter().map(|(o, u)| (o.clone(), PathBuf::from(PathBuf::from(u).file_name().unwrap()).clone())).collect(),
+            downloads: downloads,
             download_urls: download_urls,
+            torrent_urls: torrent_urls,
+            file_sizes: file_sizes,
+            md5s: md5s,
             logo: p.logo.clone(),
             image: p.image.clone(),
             screenshots: p.carousel_content.screenshot.clone(),
@@ -126,159 +425,660 @@ impl From<Vec<Product>> for Games {
 
 impl Trove {
     pub fn new(config: &Config, cache: &Cache) -> Result<Trove, Error> {
-        let text = cache.retrieve("https://www.humblebundle.com/monthly/trove")?;
-        let doc = Document::from(str::from_utf8(&text).unwrap());
-        let data = doc
-            .find(Attr("id", "webpack-monthly-trove-data"))
-            .next()
-            .unwrap()
-            .text();
-        let mut data: Feed = serde_json::from_str(data.as_str()).unwrap();
-        data.standard_products = get_products(&cache)?;
+        let mut missing = Vec::new();
+        let text = match cache.retrieve(TROVE_URL) {
+            Ok(text) => Some(text),
+            Err(CacheError::NotCached(url)) => {
+                missing.push(url);
+                None
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let (products, pages) = get_products(&cache, &mut missing)?;
+        let mut data = match text {
+            Some(text) => parse_feed(&text)?,
+            None => {
+                report_missing(&missing);
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("{} is not cached, run once online to fetch it", TROVE_URL),
+                ));
+            }
+        };
+        data.standard_products = products;
         data.standard_products.sort_by_key(|p| p.date_added);
         data.standard_products.reverse();
+        let mut platforms = config.trove.platforms.clone();
+        platforms.sort_by_key(|platform| {
+            data.download_platform_order
+                .iter()
+                .position(|p| p == platform)
+                .unwrap_or(usize::MAX)
+        });
+
+        let mut catalog = Catalog::load(&config.trove.root)?;
+        if missing.is_empty() {
+            // Dated by the newest chunk page so that restarting without refetching does not
+            // mark the same feed seen again on a later day.
+            let fetched = pages
+                .iter()
+                .map(|url| cache.fetched_at(url))
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .max()
+                .unwrap_or(UNIX_EPOCH);
+            let fetched = DateTime::<Local>::from(fetched).format("%Y-%m-%d").to_string();
+            if catalog.last_merged().map_or(true, |last| last <= fetched.as_str()) {
+                catalog.merge(&data.standard_products, &fetched);
+                catalog.save()?;
+            }
+        } else {
+            eprintln!("Feed incomplete, catalog not updated.");
+        }
+        let mut games: Games = data.standard_products.clone().into();
+        for (name, entry) in catalog.entries.iter() {
+            let game = games
+                .entry(name.clone())
+                .or_insert_with(|| (&entry.product).into());
+            game.last_seen_on = entry.last_seen.clone();
+            game.removed_from_trove = entry.removed;
+        }
 
         let mut trove = Trove {
             feed: data.clone(),
+            feed_fetched: cache.fetched_at(TROVE_URL)?,
             downloads: config.system.downloads.clone(),
             cache: config.system.cache.clone(),
             root: config.trove.root.clone(),
+            download_base: config.trove.download_base.clone(),
+            platforms: platforms,
+            torrent_watch: config.trove.torrent_watch.clone(),
             number_downloaded: 0,
             total: 0,
-            games: data.standard_products.into()
+            games: games,
+            missing: missing,
+            catalog: catalog,
+            queue: Queue::load(&config.trove.root)?,
+            download_concurrency: config.trove.download_concurrency,
         };
-        assert!(trove.root.exists());
         trove.update_download_status();
-        trove.feed.standard_products.iter().for_each(|product| {
-            if let Err(err) = cache.retrieve(&product.image) {
-                println!("Warning: {}", err);
+        for product in trove.feed.standard_products.iter() {
+            match cache.retrieve(&product.image) {
+                Err(CacheError::NotCached(url)) => trove.missing.push(url),
+                Err(err) => eprintln!("Warning: {}", err),
+                Ok(_) => {}
             }
-        });
-        println!(
-            "Downloaded: {}; Total: {}",
-            &trove.number_downloaded, &trove.total
-        );
+        }
         Ok(trove)
     }
 
     pub fn update_download_status(&mut self) {
         let mut count = 0;
+        let mut total = 0;
         for (_, game) in self.games.iter_mut() {
-            let installer = game.downloads["windows"]
-                .to_str()
-                .unwrap();
-            game.downloaded = self.root.join(installer).exists();
-            if game.downloaded {
-                count += 1;
+            let root = &self.root;
+            game.downloaded = game
+                .downloads
+                .iter()
+                .map(|(platform, installer)| (platform.clone(), root.join(installer).exists()))
+                .collect();
+            match game.is_downloaded(&self.platforms) {
+                Some(true) => {
+                    count += 1;
+                    total += 1;
+                }
+                Some(false) => total += 1,
+                None => {}
             }
         }
         self.number_downloaded = count;
-        self.total = self.games.len() as u32;
+        self.total = total;
     }
 
+    /// Games whose tracked installers are all in the trove root, by human name.
     pub fn downloaded(&self) -> Vec<&Game> {
-        (&self.games)
+        let mut games: Vec<&Game> = (&self.games)
             .iter()
             .map(|(_, g)| g)
-            .filter(|g| g.downloaded)
-            .collect()
+            .filter(|g| g.is_downloaded(&self.platforms) == Some(true))
+            .collect();
+        games.sort_by(|a, b| a.human_name.cmp(&b.human_name));
+        games
     }
 
+    /// Games whose tracked installers are all not yet, by human name.
     pub fn not_downloaded(&self) -> Vec<&Game> {
-        (&self.games)
+        let mut games: Vec<&Game> = (&self.games)
             .iter()
             .map(|(_, g)| g)
-            .filter(|g| !g.downloaded)
-            .collect()
+            .filter(|g| g.is_downloaded(&self.platforms) == Some(false))
+            .collect();
+        games.sort_by(|a, b| a.human_name.cmp(&b.human_name));
+        games
     }
 
     pub fn cache_thumbnails(&self, cache: &Cache) {
-        (&self.games)
+        let urls: Vec<String> = (&self.games)
             .iter()
             .map(|(_, g)| g)
-            .flat_map(|p| &p.thumbnails)
-            .for_each(|url| {
-                println!("Caching {}", url.as_str());
-                if let Err(err) = cache.retrieve(url.as_str()) {
-                    println!("Warning: {}", err);
-                }
-            });
+            .flat_map(|p| p.thumbnails.clone())
+            .collect();
+        report(cache.retrieve_all(&urls, |_, _| Ok(())));
     }
 
     pub fn cache_screenshots(&self, cache: &Cache) {
-        (&self.games)
+        let urls: Vec<String> = (&self.games)
             .iter()
             .map(|(_, g)| g)
-            .flat_map(|p| &p.screenshots)
-            .for_each(|url| {
-                println!("Caching {}", url.as_str());
-                if let Err(err) = cache.retrieve(url.as_str()) {
-                    println!("Warning: {}", err);
-                }
-            });
+            .flat_map(|p| p.screenshots.clone())
+            .collect();
+        report(cache.retrieve_all(&urls, |_, _| Ok(())));
     }
 
     /// Save current trove game metadata to disk
     /// Pull down copies of all game related images
-    /// TODO: Throttle or rate limit this method
+    /// Requests are paced by the cache's per host rate limit.
     pub fn cache_all_metadata(&self, cache: &Cache) -> Result<(), Error> {
         let metadata_root = self.root.join("metadata/");
         assert!(metadata_root.exists());
+        let mut urls = Vec::new();
+        let mut targets = Vec::new();
         for (name, game) in self.games.iter() {
             match url_path_ext(game.image.clone()) {
                 None => println!("{} has no extension.", &game.image),
                 Some(ext) => {
-                    println!("{} is the ext for {}", ext, &game.image);
-                    let image_filename = metadata_root.join(format!("{}.{}", name, ext));
-                    fs::write(image_filename, cache.retrieve(&game.image)?)?;
+                    urls.push(game.image.clone());
+                    targets.push(format!("{}.{}", name, ext));
                 }
             }
             if let Some(logo) = &game.logo {
                 match url_path_ext(logo.clone()) {
                     None => println!("{} has no extension.", &logo),
                     Some(ext) => {
-                        println!("{} is the ext for {}", ext, &logo);
-                        let image_filename = metadata_root.join(format!("{}_logo.{}", name, ext));
-                        fs::write(image_filename, cache.retrieve(&logo)?)?;
+                        urls.push(logo.clone());
+                        targets.push(format!("{}_logo.{}", name, ext));
                     }
                 }
             }
             for (index, url) in game.thumbnails.iter().enumerate() {
-                let img_format = match extension(url) {
-                    Some(ext) => ext,
-                    None => panic!("image doesn't have an extension"),
-                };
-                let target = format!("{}_t{}.{}", name, index, img_format);
-                fs::write(metadata_root.join(target), cache.retrieve(url)?)?;
+                match extension(url) {
+                    None => println!("{} has no extension.", &url),
+                    Some(ext) => {
+                        urls.push(url.clone());
+                        targets.push(format!("{}_t{}.{}", name, index, ext));
+                    }
+                }
             }
             for (index, url) in game.screenshots.iter().enumerate() {
-                let img_format = match extension(url) {
-                    Some(ext) => ext,
-                    None => panic!("image doesn't have an extension"),
-                };
-                let target = format!("{}_s{}.{}", name, index, img_format);
-                fs::write(metadata_root.join(target), cache.retrieve(url)?)?;
+                match extension(url) {
+                    None => println!("{} has no extension.", &url),
+                    Some(ext) => {
+                        urls.push(url.clone());
+                        targets.push(format!("{}_s{}.{}", name, index, ext));
+                    }
+                }
             }
         }
+        report(cache.retrieve_all(&urls, |index, body| {
+            fs::write(metadata_root.join(&targets[index]), body)?;
+            Ok(())
+        }));
         Ok(())
     }
 
+    /// Returns the absolute url of an installer, resolving urls relative to `download_base`.
+    fn resolve_download_url(&self, url: &str) -> Result<String, TroveError> {
+        match (url::Url::parse(url), &self.download_base) {
+            (Ok(url), _) => Ok(url.to_string()),
+            (Err(url::ParseError::RelativeUrlWithoutBase), Some(base)) => {
+                Ok(url::Url::parse(base)?.join(url)?.to_string())
+            }
+            (Err(url::ParseError::RelativeUrlWithoutBase), None) => {
+                Err(TroveError::NoDownloadBase(url.to_string()))
+            }
+            (Err(err), _) => Err(TroveError::InvalidUrl(err)),
+        }
+    }
+
+    /// Queues the missing tracked installers of `machine_name`. Returns how many were added.
+    pub fn enqueue(&mut self, machine_name: &str) -> Result<usize, TroveError> {
+        let missing = self.game(machine_name)?.missing_platforms(&self.platforms);
+        let added = missing
+            .iter()
+            .filter(|platform| self.queue.enqueue(machine_name, platform))
+            .count();
+        self.queue.save()?;
+        Ok(added)
+    }
+
+    /// Queues every game still to be downloaded whose machine or human name contains
+    /// `filter`, ignoring case.
+    pub fn enqueue_matching(&mut self, filter: &str) -> Result<usize, TroveError> {
+        let filter = filter.to_lowercase();
+        let names: Vec<String> = self
+            .priority()
+            .iter()
+            .map(|risk| risk.game)
+            .filter(|game| {
+                game.machine_name.to_lowercase().contains(&filter)
+                    || game.human_name.to_lowercase().contains(&filter)
+            })
+            .map(|game| game.machine_name.clone())
+            .collect();
+        let mut added = 0;
+        for name in names.iter() {
+            added += self.enqueue(name)?;
+        }
+        Ok(added)
+    }
+
+    /// Queues every game still to be downloaded, most at risk of removal first.
+    pub fn enqueue_missing(&mut self) -> Result<usize, TroveError> {
+        self.enqueue_matching("")
+    }
+
+    fn plan_candidate(&self, machine_name: &str, platform: &str) -> Option<(&Game, String, u64)> {
+        let game = self.games.get(machine_name)?;
+        let dest = self.root.join(game.downloads.get(platform)?);
+        let size = game.file_sizes.get(platform)?;
+        Some((game, platform.to_string(), size.saturating_sub(Downloader::resumable(&dest))))
+    }
+
+    /// Plans the missing installers of every game still to be downloaded, most at risk of
+    /// removal first, within `budget` bytes or the free space in `root`.
+    pub fn plan(&self, budget: Option<u64>) -> Plan {
+        let candidates = self
+            .priority()
+            .iter()
+            .flat_map(|risk| {
+                risk.game
+                    .missing_platforms(&self.platforms)
+                    .into_iter()
+                    .filter_map(move |platform| self.plan_candidate(&risk.game.machine_name, &platform))
+            })
+            .collect();
+        let budget = budget.unwrap_or_else(|| free_space(&self.root).unwrap_or(u64::MAX));
+        Plan::new(candidates, budget)
+    }
+
+    /// Checks the selected machine name and platform pairs against the free space in `root`.
+    pub fn preflight(&self, selection: &[(String, String)]) -> Plan {
+        let candidates = selection
+            .iter()
+            .filter_map(|(machine_name, platform)| self.plan_candidate(machine_name, platform))
+            .collect();
+        Plan::new(candidates, free_space(&self.root).unwrap_or(u64::MAX))
+    }
+
+    /// Prints a warning when the selected installers do not all fit in `root`. Returns false
+    /// when none of them does.
+    pub fn check_space(&self, selection: &[(String, String)]) -> bool {
+        let plan = self.preflight(selection);
+        if !plan.fits() {
+            println!(
+                "Warning: downloads need {} but only {} is free in {}",
+                format_size(plan.total),
+                format_size(plan.budget),
+                self.root.display()
+            );
+        }
+        !plan.nothing_fits()
+    }
+
+    /// Downloads the pending items of the queue, `download_concurrency` at a time. An item is
+    /// skipped when the trove root lacks the free space for it.
+    pub fn run_queue(&mut self, downloader: &Downloader) -> Result<(), Error> {
+        let pending: Vec<(String, String)> = self
+            .queue
+            .items
+            .iter()
+            .filter(|item| item.status == Status::Pending)
+            .map(|item| (item.machine_name.clone(), item.platform.clone()))
+            .collect();
+        // A missing download base fails every relative url alike, so it fails the whole queue
+        // before any download starts. Other bad urls only fail their own item.
+        for (machine_name, platform) in pending.iter() {
+            if let Some(url) = self.games.get(machine_name).and_then(|game| game.download_urls.get(platform)) {
+                if let Err(err @ TroveError::NoDownloadBase(_)) = self.resolve_download_url(url) {
+                    return Err(err.into());
+                }
+            }
+        }
+        if !self.check_space(&pending) {
+            return Err(Error::new(ErrorKind::Other, "none of the queued downloads fit"));
+        }
+        let concurrency = self.download_concurrency.max(1);
+        let mut jobs = HashMap::new();
+        for (index, item) in self.queue.items.iter().enumerate() {
+            let game = match self.games.get(&item.machine_name) {
+                Some(game) => game,
+                None => continue,
+            };
+            if let (Some(url), Some(installer), Some(size)) = (
+                game.download_urls.get(&item.platform),
+                game.downloads.get(&item.platform),
+                game.file_sizes.get(&item.platform),
+            ) {
+                let url = self.resolve_download_url(url);
+                jobs.insert(index, (url, self.root.join(installer), *size));
+            }
+        }
+        let root = &self.root;
+        // The queue and the bytes promised to downloads in flight.
+        let state = Mutex::new((&mut self.queue, 0u64));
+        let finish = |index: usize, status: Status| {
+            let mut state = state.lock().unwrap();
+            let item = &mut state.0.items[index];
+            println!("{} {}: {}", item.machine_name, item.platform, status);
+            item.status = status;
+            if let Err(err) = state.0.save() {
+                println!("Warning: saving queue: {}", err);
+            }
+        };
+        thread::scope(|scope| {
+            for _ in 0..concurrency {
+                scope.spawn(|| loop {
+                    let (index, needed) = {
+                        let mut state = state.lock().unwrap();
+                        let index = match state.0.start_next() {
+                            Some(index) => index,
+                            None => break,
+                        };
+                        let needed = match jobs.get(&index) {
+                            Some((_, dest, size)) => size.saturating_sub(Downloader::resumable(dest)),
+                            None => 0,
+                        };
+                        let free = fs2::available_space(root).unwrap_or(u64::MAX);
+                        if free.saturating_sub(state.1) < needed {
+                            drop(state);
+                            finish(
+                                index,
+                                Status::Skipped(format!(
+                                    "needs {}, {} free",
+                                    format_size(needed),
+                                    format_size(free)
+                                )),
+                            );
+                            continue;
+                        }
+                        state.1 += needed;
+                        if let Err(err) = state.0.save() {
+                            println!("Warning: saving queue: {}", err);
+                        }
+                        (index, needed)
+                    };
+                    let result = match jobs.get(&index) {
+                        Some((Ok(url), dest, _)) => match concurrency {
+                            1 => downloader.download(url, dest),
+                            _ => downloader.download_quietly(url, dest),
+                        }
+                        .map_err(|err| err.to_string()),
+                        Some((Err(err), _, _)) => Err(err.to_string()),
+                        None => Err("no such game or installer".to_string()),
+                    };
+                    state.lock().unwrap().1 -= needed;
+                    finish(
+                        index,
+                        match result {
+                            Ok(_) => Status::Done,
+                            Err(err) => Status::Failed(err),
+                        },
+                    );
+                });
+            }
+        });
+        self.update_download_status();
+        Ok(())
+    }
+
+    fn game(&self, machine_name: &str) -> Result<&Game, TroveError> {
+        self.games
+            .get(machine_name)
+            .ok_or_else(|| TroveError::UnknownGame(machine_name.to_string()))
+    }
+
+    /// Downloads the missing installers of every tracked platform of `machine_name`.
+    pub fn download(&mut self, downloader: &Downloader, machine_name: &str) -> Result<(), TroveError> {
+        let game = self.game(machine_name)?;
+        let missing = game.missing_platforms(&self.platforms);
+        for platform in missing.iter() {
+            if let Some(url) = game.download_urls.get(platform) {
+                self.resolve_download_url(url)?;
+            }
+        }
+        let selection: Vec<(String, String)> = missing
+            .iter()
+            .map(|platform| (machine_name.to_string(), platform.clone()))
+            .collect();
+        if !self.check_space(&selection) {
+            return Err(TroveError::NotEnoughSpace(self.root.clone()));
+        }
+        for platform in missing.iter() {
+            self.download_platform(downloader, machine_name, platform)?;
+        }
+        Ok(())
+    }
+
+    /// Downloads the `platform` installer of `machine_name` into `root` and marks it downloaded.
+    pub fn download_platform(
+        &mut self,
+        downloader: &Downloader,
+        machine_name: &str,
+        platform: &str,
+    ) -> Result<(), TroveError> {
+        let game = self.game(machine_name)?;
+        let (url, installer) = match (game.download_urls.get(platform), game.downloads.get(platform)) {
+            (Some(url), Some(installer)) => (url, installer),
+            _ => {
+                return Err(TroveError::NoInstaller {
+                    machine_name: machine_name.to_string(),
+                    platform: platform.to_string(),
+                })
+            }
+        };
+        let url = self.resolve_download_url(url)?;
+        let dest = self.root.join(installer);
+        downloader.download(&url, &dest)?;
+        self.update_download_status();
+        Ok(())
+    }
+
+    /// Retrieves the .torrent of every missing tracked installer that has one. Returns the
+    /// installer file name with the torrent's contents.
+    pub fn torrents(&self, cache: &Cache) -> Vec<(PathBuf, Vec<u8>)> {
+        let mut torrents = Vec::new();
+        for game in self.not_downloaded() {
+            for platform in game.missing_platforms(&self.platforms) {
+                let url = match game.torrent_urls.get(&platform) {
+                    Some(url) => url,
+                    None => {
+                        println!("{} has no {} torrent", game.machine_name, platform);
+                        continue;
+                    }
+                };
+                match self
+                    .resolve_download_url(url)
+                    .and_then(|url| Ok(cache.retrieve(&url)?))
+                {
+                    Ok(torrent) => torrents.push((game.downloads[&platform].clone(), torrent)),
+                    Err(err) => println!("Warning: {}: {}", game.machine_name, err),
+                }
+            }
+        }
+        torrents
+    }
+
+    /// Writes `<installer>.torrent` into `dir` for every missing tracked installer. Pointing
+    /// this at a torrent client's watch folder hands the downloads over to the client.
+    pub fn export_torrents(&self, cache: &Cache, dir: &Path) -> Result<Vec<PathBuf>, Error> {
+        let mut exported = Vec::new();
+        for (installer, torrent) in self.torrents(cache) {
+            let mut name = installer.into_os_string();
+            name.push(".torrent");
+            let path = dir.join(name);
+            fs::write(&path, torrent)?;
+            exported.push(path);
+        }
+        Ok(exported)
+    }
+
+    /// Returns a magnet link for every missing tracked installer that has a torrent.
+    pub fn magnets(&self, cache: &Cache) -> Vec<String> {
+        self.torrents(cache)
+            .iter()
+            .filter_map(|(installer, torrent)| {
+                let magnet = torrent::magnet(torrent);
+                if magnet.is_none() {
+                    println!("Warning: unreadable torrent for {}", installer.display());
+                }
+                magnet
+            })
+            .collect()
+    }
+
+    /// Checks the size and md5 of the tracked installers in `root` against the feed.
+    /// Returns the machine name, platform and result of each.
+    pub fn verify(&self) -> Result<Vec<(String, String, Verification)>, Error> {
+        let mut verifier = Verifier::new(&self.root);
+        let mut names: Vec<&String> = self.games.keys().collect();
+        names.sort();
+        let mut results = Vec::new();
+        for name in names {
+            let game = &self.games[name];
+            for platform in game.tracked(&self.platforms) {
+                let verification = verifier.verify(
+                    &self.root.join(&game.downloads[platform]),
+                    game.file_sizes[platform],
+                    &game.md5s[platform],
+                )?;
+                results.push((name.clone(), platform.clone(), verification));
+            }
+        }
+        verifier.save()?;
+        Ok(results)
+    }
+
+    /// Removes an installer that failed verification and downloads it again.
+    pub fn redownload(&mut self, downloader: &Downloader, machine_name: &str, platform: &str) -> Result<(), TroveError> {
+        if let Some(installer) = self.game(machine_name)?.downloads.get(platform) {
+            let installer = self.root.join(installer);
+            println!("Removing {}", installer.display());
+            fs::remove_file(installer)?;
+        }
+        self.download_platform(downloader, machine_name, platform)
+    }
+
+    /// Compares the chunk pages as they were `back` fetches ago with the latest ones.
+    pub fn diff(&self, cache: &Cache, back: usize) -> Result<FeedDiff, TroveError> {
+        let old = get_previous_products(cache, back)?;
+        let new = get_previous_products(cache, 0)?;
+        Ok(trove_feed::diff(&old, &new))
+    }
+
+    /// Time until the feed's next scheduled addition, when games tend to rotate out.
+    /// The feed's countdown is as of its fetch, so the time since then is taken off. It is
+    /// negative once the addition is past.
+    pub fn until_next_addition(&self) -> Option<chrono::Duration> {
+        let options = &self.feed.countdown_timer_options;
+        let parse = |time: &str| {
+            DateTime::parse_from_rfc3339(time)
+                .map(|t| t.naive_utc())
+                .or_else(|_| NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M:%S%.f"))
+                .ok()
+        };
+        let elapsed = SystemTime::now().duration_since(self.feed_fetched).unwrap_or_default();
+        let elapsed = chrono::Duration::from_std(elapsed).ok()?;
+        Some(parse(&options.next_addition_time)? - parse(&options.current_time)? - elapsed)
+    }
+
+    /// Ranks the games still to be downloaded by their risk of removal: games at risk come
+    /// first when the next addition is close, then games that have rotated out before, then
+    /// the oldest.
+    pub fn priority(&self) -> Vec<Risk> {
+        let today = Local::now().date_naive();
+        let days = |seconds: u32| {
+            let added = Local.timestamp_opt(seconds as i64, 0).single();
+            added.map_or(0, |added| (today - added.date_naive()).num_days())
+        };
+        let removed_ages: Vec<i64> = self
+            .catalog
+            .entries
+            .values()
+            .flat_map(|entry| {
+                let added = Local.timestamp_opt(entry.product.date_added as i64, 0).single();
+                entry.removal_dates.iter().filter_map(move |removed_on| {
+                    let removed_on = NaiveDate::parse_from_str(removed_on, "%Y-%m-%d").ok()?;
+                    Some((removed_on - added?.date_naive()).num_days())
+                })
+            })
+            .collect();
+        let youngest_removed = removed_ages.iter().min();
+        let until_next = self.until_next_addition().map(|until| until.num_days().max(0));
+        let soon = until_next.map_or(false, |days| days <= ADDITION_SOON_DAYS);
+        let mut ranked: Vec<Risk> = self
+            .not_downloaded()
+            .into_iter()
+            .filter(|game| !game.removed_from_trove)
+            .map(|game| {
+                let age = days(game.date_added);
+                Risk {
+                    game: game,
+                    age: age,
+                    removals: self
+                        .catalog
+                        .entries
+                        .get(&game.machine_name)
+                        .map_or(0, |entry| entry.removals),
+                    at_risk: youngest_removed
+                        .map_or(false, |youngest| age + until_next.unwrap_or(0) >= *youngest),
+                }
+            })
+            .collect();
+        ranked.sort_by(|a, b| {
+            (soon && b.at_risk)
+                .cmp(&(soon && a.at_risk))
+                .then((b.removals > 0).cmp(&(a.removals > 0)))
+                .then(b.age.cmp(&a.age))
+                .then(a.game.machine_name.cmp(&b.game.machine_name))
+        });
+        ranked
+    }
+
+    /// Games that have been pulled from the trove, most recently removed first.
+    pub fn removed(&self) -> Vec<&Game> {
+        let mut removed: Vec<&Game> = (&self.games)
+            .iter()
+            .map(|(_, g)| g)
+            .filter(|g| g.removed_from_trove)
+            .collect();
+        removed.sort_by(|a, b| b.last_seen_on.cmp(&a.last_seen_on));
+        removed
+    }
+
     pub fn format(&self, g: &Game) -> String {
-        format!("{} {} {}", g.date_added, g.human_name, g.downloaded)
+        let status: Vec<String> = g
+            .tracked(&self.platforms)
+            .iter()
+            .map(|platform| format!("{}:{}", platform, g.downloaded.get(*platform) == Some(&true)))
+            .collect();
+        let removed = match g.removed_from_trove {
+            true => format!(" (removed, last seen {})", g.last_seen_on),
+            false => "".to_string(),
+        };
+        format!("{} {} {}{}", g.date_added, g.human_name, status.join(" "), removed)
     }
 
     pub fn stray_downloads(&self) -> Vec<PathBuf> {
         let downloads = Path::new(&self.downloads);
-        assert!(downloads.exists());
         (&self.games)
             .iter()
             .map(|(_, g)| g)
-            .filter_map(|game| {
-                let installer = Path::new(&game.downloads["windows"])
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap();
+            .flat_map(|game| {
+                game.tracked(&self.platforms)
+                    .into_iter()
+                    .map(move |platform| &game.downloads[platform])
+            })
+            .filter_map(|installer| {
                 let full_installer_path = downloads.join(&installer);
                 match full_installer_path.exists() {
                     true => Some(full_installer_path),
@@ -288,6 +1088,26 @@ impl Trove {
             .collect()
     }
 
+    /// Starts watching `downloads` for tracked installers finished after startup.
+    pub fn watch_downloads(&self) -> Result<DownloadWatcher, notify::Error> {
+        let mut expected = HashMap::new();
+        for game in self.games.values() {
+            for platform in game.tracked(&self.platforms) {
+                let installer = game.downloads[platform].to_string_lossy().into_owned();
+                expected.insert(
+                    installer,
+                    Expected {
+                        machine_name: game.machine_name.clone(),
+                        platform: platform.clone(),
+                        size: game.file_sizes[platform],
+                        md5: game.md5s[platform].clone(),
+                    },
+                );
+            }
+        }
+        DownloadWatcher::new(&self.downloads, &self.root, expected)
+    }
+
     pub fn move_downloads(&self) -> Vec<PathBuf> {
         self.stray_downloads()
             .iter()
@@ -324,3 +1144,204 @@ impl Trove {
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{self, System};
+    use crate::fetcher::FileFetcher;
+    use crate::util::test_dir;
+
+    /// A config rooted in a fresh directory, with a cache answering from the recorded pages
+    /// in `tests/fixtures`.
+    fn recorded(name: &str, offline: bool) -> (Config, Cache) {
+        let dir = test_dir(name);
+        fs::create_dir_all(dir.join("root").join("metadata")).unwrap();
+        fs::create_dir_all(dir.join("downloads")).unwrap();
+        let config = Config {
+            trove: config::Trove {
+                root: dir.join("root"),
+                download_base: None,
+                platforms: vec!["linux".to_string(), "windows".to_string()],
+                torrent_watch: None,
+                download_concurrency: 1,
+            },
+            system: System {
+                downloads: dir.join("downloads"),
+                cache: dir.join("cache"),
+            },
+            cache: config::Cache {
+                offline,
+                ..config::Cache::default()
+            },
+        };
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures");
+        let cache = Cache::with_fetcher(&config.system.cache, &config.cache, Box::new(FileFetcher::new(fixtures)));
+        (config, cache)
+    }
+
+    fn names(games: Vec<&Game>) -> Vec<&str> {
+        games.iter().map(|game| game.machine_name.as_str()).collect()
+    }
+
+    #[test]
+    fn new_from_recorded_pages() {
+        let (config, cache) = recorded("trove-new", false);
+        let trove = Trove::new(&config, &cache).unwrap();
+        assert!(trove.missing.is_empty(), "{:?}", trove.missing);
+        let products: Vec<&str> = trove
+            .feed
+            .standard_products
+            .iter()
+            .map(|product| product.machine_name.as_str())
+            .collect();
+        assert_eq!(products, ["gamethree", "gametwo", "gameone"]);
+        assert_eq!(trove.platforms, ["windows", "linux"]);
+        assert_eq!(names(trove.not_downloaded()), ["gameone", "gamethree", "gametwo"]);
+        assert_eq!(trove.games["gametwo"].downloads["linux"], PathBuf::from("gametwo_linux.zip"));
+        let until = trove.until_next_addition().unwrap();
+        assert!(until <= chrono::Duration::days(3) && until > chrono::Duration::days(3) - chrono::Duration::minutes(1));
+        assert_eq!(trove.catalog.entries.len(), 3);
+        assert!(trove.catalog.removed().is_empty());
+    }
+
+    #[test]
+    fn next_addition_counts_down_from_fetch() {
+        let (config, cache) = recorded("trove-countdown", false);
+        let mut trove = Trove::new(&config, &cache).unwrap();
+        trove.feed_fetched = SystemTime::now() - Duration::from_secs(86400 + 3600);
+        let until = trove.until_next_addition().unwrap();
+        assert_eq!(until.num_days(), 1);
+        assert_eq!(until.num_hours(), 46);
+        trove.feed_fetched = SystemTime::now() - Duration::from_secs(4 * 86400);
+        assert!(trove.until_next_addition().unwrap() < chrono::Duration::zero());
+    }
+
+    #[test]
+    fn cache_all_metadata_from_recorded_pages() {
+        let (config, cache) = recorded("trove-metadata", false);
+        let trove = Trove::new(&config, &cache).unwrap();
+        trove.cache_all_metadata(&cache).unwrap();
+        let metadata = config.trove.root.join("metadata");
+        for (file, recorded) in [
+            ("gameone.png", "gameone.png"),
+            ("gameone_logo.png", "gameone_logo.png"),
+            ("gameone_t0.jpg", "gameone_thumb.jpg"),
+            ("gameone_s0.jpg", "gameone_shot.jpg"),
+            ("gametwo.png", "gametwo.png"),
+            ("gamethree.png", "gamethree.png"),
+            ("gamethree_logo.png", "gamethree_logo.png"),
+        ]
+        .iter()
+        {
+            let body = fs::read_to_string(metadata.join(file)).unwrap();
+            assert_eq!(body, format!("{}\n", recorded));
+        }
+        assert_eq!(fs::read_dir(&metadata).unwrap().count(), 7);
+    }
+
+    #[test]
+    fn new_offline_from_cached_pages() {
+        let (config, cache) = recorded("trove-offline", false);
+        Trove::new(&config, &cache).unwrap();
+        let offline = Cache::with_fetcher(
+            &config.system.cache,
+            &config::Cache {
+                offline: true,
+                ..config::Cache::default()
+            },
+            Box::new(FileFetcher::new(test_dir("trove-offline-empty"))),
+        );
+        let trove = Trove::new(&config, &offline).unwrap();
+        assert!(trove.missing.is_empty(), "{:?}", trove.missing);
+        assert_eq!(trove.games.len(), 3);
+    }
+
+    #[test]
+    fn new_offline_without_trove_page() {
+        let (config, cache) = recorded("trove-uncached", true);
+        let err = Trove::new(&config, &cache).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert!(err.to_string().contains(TROVE_URL), "{}", err);
+    }
+
+    #[test]
+    fn diff_finds_change_on_later_page() {
+        let (config, _) = recorded("trove-diff", false);
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures");
+        let page = config.system.cache.with_file_name("chunk_index=1");
+        let recorded_page = fixtures.join("www.humblebundle.com/api/v1/trove/chunk_index=1");
+        fs::copy(&recorded_page, &page).unwrap();
+        let mut fetcher = FileFetcher::new(fixtures);
+        fetcher.insert(&chunk_url(1), &page);
+        let cache = Cache::with_fetcher(&config.system.cache, &config.cache, Box::new(fetcher));
+        let trove = Trove::new(&config, &cache).unwrap();
+        match trove.diff(&cache, 1) {
+            Err(err) => assert!(err.to_string().contains("no earlier fetch"), "{}", err),
+            Ok(diff) => panic!("expected no earlier fetch, got {}", diff),
+        }
+
+        let rebuilt = fs::read_to_string(&recorded_page)
+            .unwrap()
+            .replace("ffeeddccbbaa99887766554433221100", "0123456789abcdef0123456789abcdef");
+        fs::write(&page, rebuilt).unwrap();
+        cache.force_retrieve(&chunk_url(1)).unwrap();
+        let diff = trove.diff(&cache, 1).unwrap();
+        assert!(diff.added.is_empty() && diff.removed.is_empty());
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].machine_name, "gamethree");
+        match trove.diff(&cache, 2) {
+            Err(err) => assert!(err.to_string().contains("only 1 earlier fetch"), "{}", err),
+            Ok(diff) => panic!("expected only 1 earlier fetch, got {}", diff),
+        }
+    }
+
+    #[test]
+    fn reads_pages_until_one_is_empty() {
+        let (config, _) = recorded("trove-pages", false);
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures");
+        let pages = fixtures.join("www.humblebundle.com/api/v1/trove");
+        let recorded_page = fs::read_to_string(pages.join("chunk_index=1")).unwrap();
+        let mut fetcher = FileFetcher::new(fixtures);
+        for i in 2..6 {
+            let page = config.system.cache.with_file_name(format!("chunk_index={}", i));
+            fs::write(&page, recorded_page.replace("gamethree", &format!("gamepage{}", i))).unwrap();
+            fetcher.insert(&chunk_url(i), page);
+        }
+        fetcher.insert(&chunk_url(6), pages.join("chunk_index=2"));
+        let cache = Cache::with_fetcher(&config.system.cache, &config.cache, Box::new(fetcher));
+        let trove = Trove::new(&config, &cache).unwrap();
+        assert!(trove.missing.is_empty(), "{:?}", trove.missing);
+        assert_eq!(trove.games.len(), 7);
+        assert!(trove.games.contains_key("gamepage5"));
+        assert_eq!(trove.catalog.entries.len(), 7);
+        assert!(trove.catalog.removed().is_empty());
+    }
+
+    #[test]
+    fn relative_download_needs_download_base() {
+        let (config, cache) = recorded("trove-download-base", false);
+        let mut trove = Trove::new(&config, &cache).unwrap();
+        let err = trove.download(&Downloader::new(), "gameone").err().unwrap();
+        assert!(err.to_string().contains("trove.download_base"), "{}", err);
+    }
+
+    #[test]
+    fn unresolvable_url_fails_only_its_item() {
+        let (config, cache) = recorded("trove-queue-urls", false);
+        let mut trove = Trove::new(&config, &cache).unwrap();
+        assert!(trove.enqueue("gameone").unwrap() > 0);
+        let err = trove.run_queue(&Downloader::new()).err().unwrap();
+        assert!(err.to_string().contains("trove.download_base"), "{}", err);
+        assert!(trove.queue.items.iter().all(|item| item.status == Status::Pending));
+
+        trove.download_base = Some("not a url".to_string());
+        trove.run_queue(&Downloader::new()).unwrap();
+        for item in trove.queue.items.iter() {
+            match &item.status {
+                Status::Failed(err) => assert!(err.contains("url"), "{}", err),
+                status => panic!("expected {} {} to fail, got {}", item.machine_name, item.platform, status),
+            }
+        }
+    }
+}
@@ -1,24 +1,26 @@
 /// This module handles the deserialization of the humble bundle monthly trove metadata feed.
-/// It provides operations that deal with the contents of the feed itself.
-use serde::Deserialize;
+/// It provides operations that deal with the contents of the feed itself, such as comparing
+/// two snapshots of it.
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
+use std::fmt;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct TimerOptions {
     pub current_time: String,
     pub next_addition_time: String,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub struct Url {
     pub web: String,
     pub bittorrent: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub struct Download {
     pub machine_name: String,
@@ -30,7 +32,7 @@ pub struct Download {
     pub size: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct CarouselContent {
     pub youtube_link: Option<Vec<String>>,
@@ -38,14 +40,14 @@ pub struct CarouselContent {
     pub screenshot: Vec<String>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct Publisher {
     pub publisher_name: String,
     pub publisher_uri: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct Product {
     pub background_image: Option<String>, // can be null
@@ -67,7 +69,7 @@ pub struct Product {
     pub youtube_link: Option<String>,       // can be null
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Feed {
     pub all_access: Vec<String>,
@@ -79,3 +81,200 @@ pub struct Feed {
 }
 
 
+
+#[derive(Debug, Clone)]
+pub enum Change {
+    /// A platform's installer was rebuilt: its md5 or file size differs.
+    Build {
+        platform: String,
+        old_md5: String,
+        new_md5: String,
+        old_size: u64,
+        new_size: u64,
+    },
+    PlatformAdded(String),
+    PlatformRemoved(String),
+    Description,
+    Image,
+    Logo,
+    Screenshots,
+    Thumbnails,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Build {
+                platform,
+                old_md5,
+                new_md5,
+                old_size,
+                new_size,
+            } => write!(
+                f,
+                "new {} build: {} ({} bytes) -> {} ({} bytes)",
+                platform, old_md5, old_size, new_md5, new_size
+            ),
+            Change::PlatformAdded(platform) => write!(f, "{} build added", platform),
+            Change::PlatformRemoved(platform) => write!(f, "{} build removed", platform),
+            Change::Description => write!(f, "description edited"),
+            Change::Image => write!(f, "image changed"),
+            Change::Logo => write!(f, "logo changed"),
+            Change::Screenshots => write!(f, "screenshots changed"),
+            Change::Thumbnails => write!(f, "thumbnails changed"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProductDiff {
+    pub machine_name: String,
+    pub human_name: String,
+    pub changes: Vec<Change>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FeedDiff {
+    pub added: Vec<Product>,
+    pub removed: Vec<Product>,
+    pub changed: Vec<ProductDiff>,
+}
+
+impl FeedDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl fmt::Display for FeedDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for product in self.added.iter() {
+            writeln!(f, "+ {} ({})", product.human_name, product.machine_name)?;
+        }
+        for product in self.removed.iter() {
+            writeln!(f, "- {} ({})", product.human_name, product.machine_name)?;
+        }
+        for product in self.changed.iter() {
+            writeln!(f, "~ {} ({})", product.human_name, product.machine_name)?;
+            for change in product.changes.iter() {
+                writeln!(f, "    {}", change)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn changes(old: &Product, new: &Product) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let mut platforms: Vec<&String> = old.downloads.keys().chain(new.downloads.keys()).collect();
+    platforms.sort();
+    platforms.dedup();
+    for platform in platforms {
+        match (old.downloads.get(platform), new.downloads.get(platform)) {
+            (Some(o), Some(n)) if o.md5 != n.md5 || o.file_size != n.file_size => {
+                changes.push(Change::Build {
+                    platform: platform.clone(),
+                    old_md5: o.md5.clone(),
+                    new_md5: n.md5.clone(),
+                    old_size: o.file_size,
+                    new_size: n.file_size,
+                })
+            }
+            (None, Some(_)) => changes.push(Change::PlatformAdded(platform.clone())),
+            (Some(_), None) => changes.push(Change::PlatformRemoved(platform.clone())),
+            _ => {}
+        }
+    }
+    if old.description_text != new.description_text {
+        changes.push(Change::Description);
+    }
+    if old.image != new.image {
+        changes.push(Change::Image);
+    }
+    if old.logo != new.logo {
+        changes.push(Change::Logo);
+    }
+    if old.carousel_content.screenshot != new.carousel_content.screenshot {
+        changes.push(Change::Screenshots);
+    }
+    if old.carousel_content.thumbnail != new.carousel_content.thumbnail {
+        changes.push(Change::Thumbnails);
+    }
+    changes
+}
+
+/// Compares two snapshots of the feed's products.
+pub fn diff(old: &[Product], new: &[Product]) -> FeedDiff {
+    let old_by_name: HashMap<&String, &Product> = old.iter().map(|p| (&p.machine_name, p)).collect();
+    let new_by_name: HashMap<&String, &Product> = new.iter().map(|p| (&p.machine_name, p)).collect();
+    let mut diff = FeedDiff::default();
+    for product in new.iter() {
+        match old_by_name.get(&product.machine_name) {
+            None => diff.added.push(product.clone()),
+            Some(previous) => {
+                let changes = changes(previous, product);
+                if !changes.is_empty() {
+                    diff.changed.push(ProductDiff {
+                        machine_name: product.machine_name.clone(),
+                        human_name: product.human_name.clone(),
+                        changes,
+                    });
+                }
+            }
+        }
+    }
+    for product in old.iter() {
+        if !new_by_name.contains_key(&product.machine_name) {
+            diff.removed.push(product.clone());
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHUNK: &str = include_str!("../tests/fixtures/www.humblebundle.com/api/v1/trove/chunk_index=0");
+
+    fn products() -> Vec<Product> {
+        serde_json::from_str(CHUNK).unwrap()
+    }
+
+    #[test]
+    fn diff_of_same_products_is_empty() {
+        assert!(diff(&products(), &products()).is_empty());
+    }
+
+    #[test]
+    fn diff_finds_added_removed_and_changed() {
+        let old = products();
+        let mut new = products();
+        let removed = new.remove(1);
+        let mut added = removed.clone();
+        added.machine_name = "gamefour".to_string();
+        new.push(added);
+        let windows = new[0].downloads.get_mut("windows").unwrap();
+        windows.md5 = "ffffffffffffffffffffffffffffffff".to_string();
+        windows.file_size = 2048;
+        new[0].description_text = "<p>Rewritten.</p>".to_string();
+
+        let diff = diff(&old, &new);
+        let names = |products: &[Product]| -> Vec<String> {
+            products.iter().map(|p| p.machine_name.clone()).collect()
+        };
+        assert_eq!(names(&diff.added), ["gamefour"]);
+        assert_eq!(names(&diff.removed), [removed.machine_name]);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].machine_name, "gameone");
+        let changes: Vec<String> = diff.changed[0].changes.iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            changes,
+            [
+                "new windows build: 0123456789abcdef0123456789abcdef (1024 bytes) -> \
+                 ffffffffffffffffffffffffffffffff (2048 bytes)",
+                "description edited",
+            ]
+        );
+    }
+}
@@ -1,6 +1,7 @@
-use std::path::{PathBuf};
-use std::fs::{File};
+use std::path::{Path, PathBuf};
+use std::fs::{self, File};
 use std::io::{Read, Write, Error};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use url::{Url, ParseError};
 
 pub fn create_file(name: PathBuf, contents: &str) -> Result<(), Error> {
@@ -17,6 +18,34 @@ pub fn copy_to_file(name: PathBuf, buffer: &Vec<u8>) -> Result<(), Error> {
     Ok(())
 }
 
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes `contents` to a temporary file next to `name` and renames it into place, so that
+/// readers never observe a partially written file. The temporary name is unique to the process
+/// and the call, as several tarnish processes may share a cache or trove.
+pub fn write_atomic(name: &Path, contents: &[u8]) -> Result<(), Error> {
+    let mut tmp = name.as_os_str().to_owned();
+    tmp.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::SeqCst)
+    ));
+    let tmp = PathBuf::from(tmp);
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp, name)
+}
+
+/// Moves `from` to `to`, copying and removing it when they are on different file systems.
+pub fn move_file(from: &Path, to: &Path) -> Result<(), Error> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to)?;
+    fs::remove_file(from)
+}
+
 pub fn read_file(name: PathBuf) -> Result<Vec<u8>, Error> {
     let mut buffer = Vec::new();
     File::open(name)?
@@ -24,6 +53,17 @@ pub fn read_file(name: PathBuf) -> Result<Vec<u8>, Error> {
     Ok(buffer)
 }
 
+pub fn format_size(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, units[unit])
+}
+
 pub fn url_path(url: &str) -> Result<String, ParseError> {
     Ok(Url::parse(url)?.path().to_string().clone())
 }
@@ -49,3 +89,11 @@ pub fn extension(name: &str) -> Option<String> {
     }*/
 }
 
+/// An empty directory under the system temp dir for the test `name`.
+#[cfg(test)]
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tarnish-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,146 @@
+/// This module checks downloaded installers against the size and md5 published in the feed.
+/// Hashing multi gigabyte installers is slow, so results are remembered in a
+/// `.verified.json` file keyed by path and only recomputed when a file's size or
+/// modification time changes.
+use crate::util::write_atomic;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Error, Read};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verification {
+    Verified,
+    SizeMismatch { expected: u64, actual: u64 },
+    HashMismatch { expected: String, actual: String },
+    Missing,
+}
+
+impl Verification {
+    /// Whether the file exists but is not the one published in the feed.
+    pub fn is_bad(&self) -> bool {
+        match self {
+            Verification::SizeMismatch { .. } | Verification::HashMismatch { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Verification::Verified => write!(f, "verified"),
+            Verification::SizeMismatch { expected, actual } => {
+                write!(f, "size-mismatch (expected {}, found {})", expected, actual)
+            }
+            Verification::HashMismatch { expected, actual } => {
+                write!(f, "hash-mismatch (expected {}, found {})", expected, actual)
+            }
+            Verification::Missing => write!(f, "missing"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Hashed {
+    mtime: u64,
+    size: u64,
+    md5: String,
+}
+
+pub fn md5_file(path: &Path) -> Result<String, Error> {
+    let mut file = File::open(path)?;
+    let mut context = md5::Context::new();
+    let mut buffer = vec![0; 1024 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        context.consume(&buffer[..read]);
+    }
+    Ok(format!("{:x}", context.compute()))
+}
+
+pub struct Verifier {
+    path: PathBuf,
+    hashed: HashMap<String, Hashed>,
+}
+
+impl Verifier {
+    /// Loads the remembered hashes of the installers in `root`.
+    pub fn new(root: &Path) -> Verifier {
+        let path = root.join(".verified.json");
+        let hashed = fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        Verifier { path, hashed }
+    }
+
+    /// Checks `file` against the expected size and md5, rehashing only when it changed.
+    pub fn verify(&mut self, file: &Path, size: u64, md5: &str) -> Result<Verification, Error> {
+        let metadata = match fs::metadata(file) {
+            Ok(metadata) => metadata,
+            Err(_) => return Ok(Verification::Missing),
+        };
+        if metadata.len() != size {
+            return Ok(Verification::SizeMismatch {
+                expected: size,
+                actual: metadata.len(),
+            });
+        }
+        let mtime = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let name = file.to_string_lossy().to_string();
+        let actual = match self.hashed.get(&name) {
+            Some(hashed) if hashed.mtime == mtime && hashed.size == metadata.len() => {
+                hashed.md5.clone()
+            }
+            _ => {
+                println!("Hashing {}", file.display());
+                let md5 = md5_file(file)?;
+                self.hashed.insert(
+                    name,
+                    Hashed {
+                        mtime,
+                        size: metadata.len(),
+                        md5: md5.clone(),
+                    },
+                );
+                md5
+            }
+        };
+        Ok(match actual.eq_ignore_ascii_case(md5) {
+            true => Verification::Verified,
+            false => Verification::HashMismatch {
+                expected: md5.to_string(),
+                actual,
+            },
+        })
+    }
+
+    /// Remembers the hash of `from` for `to`, where it was moved, so it is not hashed again.
+    pub fn moved(&mut self, from: &Path, to: &Path) -> Result<(), Error> {
+        if let Some(mut hashed) = self.hashed.remove(&from.to_string_lossy().to_string()) {
+            hashed.mtime = fs::metadata(to)?
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            self.hashed.insert(to.to_string_lossy().to_string(), hashed);
+        }
+        Ok(())
+    }
+
+    /// Persists the remembered hashes.
+    pub fn save(&self) -> Result<(), Error> {
+        write_atomic(&self.path, &serde_json::to_vec_pretty(&self.hashed)?)
+    }
+}
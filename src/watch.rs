@@ -0,0 +1,149 @@
+/// This module watches `system.downloads` for installers finished outside tarnish, e.g. in a
+/// browser, and moves them into the trove root once they match the size and md5 in the feed.
+/// Browsers write to a `.crdownload` or `.part` file and rename it when done, so only files
+/// under an installer's own name are considered. The watcher uses the platform's native
+/// notifications and falls back to polling where those are unavailable.
+use crate::util::move_file;
+use crate::verify::{Verification, Verifier};
+use log::warn;
+use notify::event::{AccessKind, EventKind};
+use notify::{Config, Event, PollWatcher, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// An installer the feed lists, keyed by its file name.
+pub struct Expected {
+    pub machine_name: String,
+    pub platform: String,
+    pub size: u64,
+    pub md5: String,
+}
+
+pub struct DownloadWatcher {
+    _watcher: Box<dyn Watcher + Send>,
+    /// Machine name and platform of each installer moved into the trove root.
+    pub moved: Receiver<(String, String)>,
+}
+
+impl DownloadWatcher {
+    pub fn new(
+        downloads: &Path,
+        root: &Path,
+        expected: HashMap<String, Expected>,
+    ) -> Result<DownloadWatcher, notify::Error> {
+        let (event_tx, event_rx) = channel();
+        let watcher = start(downloads, event_tx)?;
+        let (moved_tx, moved) = channel();
+        let root = root.to_path_buf();
+        thread::spawn(move || {
+            // Ends when the watcher, and with it the sending half, is dropped.
+            for event in event_rx {
+                match event {
+                    Ok(event) if is_write(&event.kind) => {
+                        for path in event.paths {
+                            check(&path, &root, &expected, &moved_tx);
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(err) => warn!("watching downloads: {}", err),
+                }
+            }
+        });
+        Ok(DownloadWatcher {
+            _watcher: watcher,
+            moved,
+        })
+    }
+}
+
+fn start(
+    downloads: &Path,
+    tx: Sender<notify::Result<Event>>,
+) -> Result<Box<dyn Watcher + Send>, notify::Error> {
+    let native = notify::recommended_watcher(tx.clone()).and_then(|mut watcher| {
+        watcher.watch(downloads, RecursiveMode::NonRecursive)?;
+        Ok(watcher)
+    });
+    match native {
+        Ok(watcher) => Ok(Box::new(watcher)),
+        Err(err) => {
+            warn!("{}: polling {} instead", err, downloads.display());
+            let config = Config::default().with_poll_interval(POLL_INTERVAL);
+            let mut watcher = PollWatcher::new(tx, config)?;
+            watcher.watch(downloads, RecursiveMode::NonRecursive)?;
+            Ok(Box::new(watcher))
+        }
+    }
+}
+
+fn is_write(kind: &EventKind) -> bool {
+    match kind {
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Any => true,
+        EventKind::Access(AccessKind::Close(_)) => true,
+        _ => false,
+    }
+}
+
+fn is_partial(path: &Path) -> bool {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("crdownload") | Some("part") => true,
+        _ => false,
+    }
+}
+
+/// Moves `path` into `root` if it is a complete, verified installer. Installers left in place
+/// are reported on stdout, like the REPL's other output, since the logger only shows errors.
+fn check(path: &Path, root: &Path, expected: &HashMap<String, Expected>, moved: &Sender<(String, String)>) {
+    if is_partial(path) {
+        return;
+    }
+    let name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name,
+        None => return,
+    };
+    let installer = match expected.get(name) {
+        Some(installer) => installer,
+        None => return,
+    };
+    // Still being written, or already moved by an earlier event.
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.len() == installer.size => {}
+        _ => return,
+    }
+    // Loaded for every installer so that hashes remembered by `verify` meanwhile are kept.
+    let mut verifier = Verifier::new(root);
+    match verifier.verify(path, installer.size, &installer.md5) {
+        Ok(Verification::Verified) => {}
+        Ok(verification) => {
+            if verification.is_bad() {
+                println!("Warning: {} {}, leaving it in place", path.display(), verification);
+            }
+            return;
+        }
+        Err(err) => {
+            println!("Warning: {}: {}", err, path.display());
+            return;
+        }
+    }
+    let dest: PathBuf = root.join(name);
+    if dest.exists() {
+        println!("Warning: {} exists, leaving {} in place", dest.display(), path.display());
+        return;
+    }
+    match move_file(path, &dest) {
+        Ok(()) => {
+            println!("Moved {} to {}.", path.display(), dest.display());
+            if let Err(err) = verifier.moved(path, &dest).and_then(|_| verifier.save()) {
+                println!("Warning: {}: {}", err, dest.display());
+            }
+            let _ = moved.send((installer.machine_name.clone(), installer.platform.clone()));
+        }
+        Err(err) => println!("Warning: moving {}: {}", path.display(), err),
+    }
+}